@@ -0,0 +1,874 @@
+use std::net::{
+    IpAddr,
+    SocketAddr,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::fs::{
+    read,
+    read_to_string,
+};
+use std::str::FromStr;
+use std::error::Error;
+use std::fmt;
+use std::ffi::OsString;
+use std::time::Duration;
+
+use clap::{
+    App,
+    Arg,
+    ArgMatches,
+    ErrorKind,
+    SubCommand,
+};
+use log::LevelFilter;
+use tiny_http::SslConfig;
+
+use crate::hash::HashAlgo;
+use crate::quota::QuotaPolicy;
+use crate::store::fs::Layout;
+
+
+/// Parameters for a garbage collection run.
+pub struct GcSettings {
+    pub grace: Duration,
+    pub pins: Option<PathBuf>,
+    /// Also keep records that mutable references pointed to before.
+    pub keep_history: bool,
+    pub dry_run: bool,
+}
+
+/// Parameters for an integrity check.
+pub struct FsckSettings {
+    /// Move corrupt records out of the store.
+    pub quarantine: bool,
+    /// Print the summary as JSON.
+    pub json: bool,
+}
+
+/// Largest request bodies accepted, in bytes, 0 meaning unlimited.
+#[derive(Debug, Clone)]
+pub struct UploadLimits {
+    pub anonymous: u64,
+    /// Applies to requests carrying an `Authorization` header.
+    pub authenticated: u64,
+}
+
+impl UploadLimits {
+    pub fn for_request(&self, authenticated: bool) -> Option<u64> {
+        let v = match authenticated {
+            true => {
+                self.authenticated
+            },
+            false => {
+                self.anonymous
+            },
+        };
+        match v {
+            0 => {
+                None
+            },
+            _ => {
+                Some(v)
+            },
+        }
+    }
+}
+
+/// Location and credentials of the bucket to store records in, instead of the data directory.
+#[cfg_attr(not(feature = "s3"), allow(dead_code))]
+pub struct S3Settings {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+/// What the process has been asked to do.
+pub enum Mode {
+    Serve,
+    Gc(GcSettings),
+    Fsck(FsckSettings),
+    /// Convert a flat data directory to the sharded layout.
+    Migrate,
+}
+
+/// Runtime settings for the server, resolved from command line flags with `WALA_*` environment
+/// variables as fallback.
+pub struct Settings {
+    pub host: IpAddr,
+    pub port: u16,
+    pub dir: PathBuf,
+    /// Layout the data directory must have, or is created with.
+    pub layout: Option<Layout>,
+    pub loglevel: LevelFilter,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub workers: usize,
+    pub queue: usize,
+    pub quota: QuotaPolicy,
+    pub upload: UploadLimits,
+    /// Algorithms every upload is also reachable by, in addition to the one it was stored with.
+    pub aliases: Vec<HashAlgo>,
+    /// Whether signatures over the request body alone are still accepted.
+    pub legacy_auth: bool,
+    /// File or directory listing the identities allowed to authenticate, any identity if unset.
+    pub trust_store: Option<PathBuf>,
+    pub s3: Option<S3Settings>,
+    pub mode: Mode,
+}
+
+#[derive(Debug)]
+pub struct SettingsError {
+    msg: String,
+}
+
+impl SettingsError {
+    fn new(msg: String) -> SettingsError {
+        SettingsError {
+            msg,
+        }
+    }
+}
+
+impl Error for SettingsError {}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(&self.msg)
+    }
+}
+
+
+fn app() -> App<'static, 'static> {
+    App::new("wala")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("content addressed storage with signed mutable references")
+        .arg(Arg::with_name("host")
+            .long("host")
+            .value_name("ADDRESS")
+            .env("WALA_HOST")
+            .default_value("0.0.0.0")
+            .help("address to bind to"))
+        .arg(Arg::with_name("port")
+            .long("port")
+            .short("p")
+            .value_name("PORT")
+            .env("WALA_PORT")
+            .default_value("8001")
+            .help("port to listen on"))
+        .arg(Arg::with_name("datadir")
+            .long("datadir")
+            .short("d")
+            .value_name("PATH")
+            .env("WALA_DATADIR")
+            .default_value(".")
+            .global(true)
+            .help("directory to store records in"))
+        .arg(Arg::with_name("layout")
+            .long("layout")
+            .value_name("LAYOUT")
+            .env("WALA_LAYOUT")
+            .possible_values(&["flat", "sharded"])
+            .global(true)
+            .help("layout of the data directory, fixed when it is first used"))
+        .arg(Arg::with_name("loglevel")
+            .long("loglevel")
+            .short("l")
+            .value_name("LEVEL")
+            .env("WALA_LOGLEVEL")
+            .default_value("info")
+            .global(true)
+            .help("log level (off, error, warn, info, debug, trace)"))
+        .arg(Arg::with_name("tls_cert")
+            .long("tls-cert")
+            .value_name("FILE")
+            .env("WALA_TLS_CERT")
+            .requires("tls_key")
+            .help("PEM certificate (chain) to serve over TLS"))
+        .arg(Arg::with_name("tls_key")
+            .long("tls-key")
+            .value_name("FILE")
+            .env("WALA_TLS_KEY")
+            .requires("tls_cert")
+            .help("PEM private key for the TLS certificate"))
+        .arg(Arg::with_name("workers")
+            .long("workers")
+            .short("w")
+            .value_name("COUNT")
+            .env("WALA_WORKERS")
+            .default_value("4")
+            .help("number of requests to process in parallel"))
+        .arg(Arg::with_name("queue")
+            .long("queue")
+            .value_name("COUNT")
+            .env("WALA_QUEUE")
+            .default_value("64")
+            .help("number of requests to hold while all workers are busy, excess requests get 503"))
+        .arg(Arg::with_name("max_upload")
+            .long("max-upload")
+            .value_name("BYTES")
+            .env("WALA_MAX_UPLOAD")
+            .default_value("10485760")
+            .help("largest anonymous upload accepted, 0 for unlimited"))
+        .arg(Arg::with_name("max_upload_auth")
+            .long("max-upload-auth")
+            .value_name("BYTES")
+            .env("WALA_MAX_UPLOAD_AUTH")
+            .default_value("104857600")
+            .help("largest authenticated upload accepted, 0 for unlimited"))
+        .arg(Arg::with_name("alias_hash")
+            .long("alias-hash")
+            .value_name("ALGO")
+            .env("WALA_ALIAS_HASH")
+            .multiple(true)
+            .number_of_values(1)
+            .use_delimiter(true)
+            .help("also make uploads reachable by their digest in this algorithm, e.g. keccak256"))
+        .arg(Arg::with_name("legacy_auth")
+            .long("legacy-auth")
+            .help("also accept signatures over the body alone, which can be replayed against any key [env: WALA_LEGACY_AUTH]"))
+        .arg(Arg::with_name("trust_store")
+            .long("trust-store")
+            .value_name("PATH")
+            .env("WALA_TRUST_STORE")
+            .help("file or directory of OpenPGP keyrings, or of lists with one hex fingerprint, key or address per line, allowed to authenticate; reread when changed"))
+        .arg(Arg::with_name("s3_endpoint")
+            .long("s3-endpoint")
+            .value_name("URL")
+            .env("WALA_S3_ENDPOINT")
+            .requires("s3_bucket")
+            .help("S3 compatible service to store records in instead of the data directory"))
+        .arg(Arg::with_name("s3_bucket")
+            .long("s3-bucket")
+            .value_name("NAME")
+            .env("WALA_S3_BUCKET")
+            .requires("s3_endpoint")
+            .help("bucket to store records in"))
+        .arg(Arg::with_name("s3_region")
+            .long("s3-region")
+            .value_name("REGION")
+            .env("WALA_S3_REGION")
+            .default_value("us-east-1")
+            .help("region of the bucket, used for request signing"))
+        .arg(Arg::with_name("s3_access_key")
+            .long("s3-access-key")
+            .value_name("KEY")
+            .env("WALA_S3_ACCESS_KEY")
+            .help("access key for the bucket"))
+        .arg(Arg::with_name("s3_secret_key")
+            .long("s3-secret-key")
+            .value_name("KEY")
+            .env("WALA_S3_SECRET_KEY")
+            .hide_env_values(true)
+            .help("secret key for the bucket"))
+        .arg(Arg::with_name("quota_bytes")
+            .long("quota-bytes")
+            .value_name("BYTES")
+            .env("WALA_QUOTA_BYTES")
+            .help("default limit on bytes held by the mutable references of an identity"))
+        .arg(Arg::with_name("quota_objects")
+            .long("quota-objects")
+            .value_name("COUNT")
+            .env("WALA_QUOTA_OBJECTS")
+            .help("default limit on the number of mutable references of an identity"))
+        .arg(Arg::with_name("quota_file")
+            .long("quota-file")
+            .value_name("FILE")
+            .env("WALA_QUOTA_FILE")
+            .help("per-identity limits, one '<fingerprint> <bytes> <objects>' per line, '-' for unlimited"))
+        .subcommand(SubCommand::with_name("gc")
+            .about("remove records no longer referenced by any mutable reference or pin")
+            .arg(Arg::with_name("grace")
+                .long("grace")
+                .value_name("SECONDS")
+                .default_value("86400")
+                .help("only remove records last written longer ago than this"))
+            .arg(Arg::with_name("pins")
+                .long("pins")
+                .value_name("FILE")
+                .help("file listing digests to keep, one per line"))
+            .arg(Arg::with_name("keep_history")
+                .long("keep-history")
+                .help("also keep records that past revisions of mutable references point to"))
+            .arg(Arg::with_name("dry_run")
+                .long("dry-run")
+                .short("n")
+                .help("only report what would be removed")))
+        .subcommand(SubCommand::with_name("fsck")
+            .about("verify that records match their digest and that mutable references point at records")
+            .arg(Arg::with_name("quarantine")
+                .long("quarantine")
+                .help("move corrupt records to the .quarantine directory"))
+            .arg(Arg::with_name("json")
+                .long("json")
+                .help("print the summary as a JSON object")))
+        .subcommand(SubCommand::with_name("migrate")
+            .about("convert a flat data directory to the sharded layout in place, with the server stopped"))
+}
+
+
+fn tls_path(m: &ArgMatches, name: &str) -> Result<Option<PathBuf>, SettingsError> {
+    let p = match m.value_of(name) {
+        Some(v) => {
+            PathBuf::from(v)
+        },
+        None => {
+            return Ok(None);
+        },
+    };
+    if cfg!(not(feature = "ssl")) {
+        return Err(SettingsError::new(String::from("TLS requested but not compiled in, rebuild with the 'ssl' feature")));
+    }
+    if !p.is_file() {
+        return Err(SettingsError::new(format!("TLS file {:?} does not exist or is not a file", p)));
+    }
+    Ok(Some(p))
+}
+
+/// Whether a flag is given, or its environment variable, passed as `env`, is set to anything but
+/// empty, `0` or `false`.
+///
+/// Flags cannot use the environment fallback of the argument parser, which would make them take
+/// a value.
+fn flag(m: &ArgMatches, name: &str, env: Option<&str>) -> bool {
+    if m.is_present(name) {
+        return true;
+    }
+    match env {
+        Some(v) => {
+            !matches!(v.trim().to_ascii_lowercase().as_str(), "" | "0" | "false")
+        },
+        None => {
+            false
+        },
+    }
+}
+
+fn gc_settings(m: &ArgMatches) -> Result<GcSettings, SettingsError> {
+    let grace_str = m.value_of("grace").unwrap();
+    let grace = match u64::from_str(grace_str) {
+        Ok(v) => {
+            Duration::from_secs(v)
+        },
+        Err(e) => {
+            return Err(SettingsError::new(format!("invalid grace period '{}': {}", grace_str, e)));
+        },
+    };
+    let pins = m.value_of("pins").map(PathBuf::from);
+    if let Some(v) = &pins {
+        if !v.is_file() {
+            return Err(SettingsError::new(format!("pin file {:?} does not exist or is not a file", v)));
+        }
+    }
+    Ok(GcSettings {
+        grace,
+        pins,
+        keep_history: m.is_present("keep_history"),
+        dry_run: m.is_present("dry_run"),
+    })
+}
+
+fn quota_limit(m: &ArgMatches, name: &str) -> Result<Option<u64>, SettingsError> {
+    let s = match m.value_of(name) {
+        Some(v) => {
+            v
+        },
+        None => {
+            return Ok(None);
+        },
+    };
+    match u64::from_str(s) {
+        Ok(v) => {
+            Ok(Some(v))
+        },
+        Err(e) => {
+            Err(SettingsError::new(format!("invalid quota '{}': {}", s, e)))
+        },
+    }
+}
+
+fn s3_settings(m: &ArgMatches) -> Result<Option<S3Settings>, SettingsError> {
+    let endpoint = match m.value_of("s3_endpoint") {
+        Some(v) => {
+            v.to_string()
+        },
+        None => {
+            return Ok(None);
+        },
+    };
+    if cfg!(not(feature = "s3")) {
+        return Err(SettingsError::new(String::from("S3 storage requested but not compiled in, rebuild with the 's3' feature")));
+    }
+    if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+        return Err(SettingsError::new(format!("invalid S3 endpoint '{}', expected an http or https url", endpoint)));
+    }
+    let access_key = m.value_of("s3_access_key");
+    let secret_key = m.value_of("s3_secret_key");
+    match (access_key, secret_key) {
+        (Some(access_key), Some(secret_key)) => {
+            Ok(Some(S3Settings {
+                endpoint,
+                bucket: m.value_of("s3_bucket").unwrap().to_string(),
+                region: m.value_of("s3_region").unwrap().to_string(),
+                access_key: access_key.to_string(),
+                secret_key: secret_key.to_string(),
+            }))
+        },
+        _ => {
+            Err(SettingsError::new(String::from("S3 storage requires both an access key and a secret key")))
+        },
+    }
+}
+
+fn upload_limit(m: &ArgMatches, name: &str) -> Result<u64, SettingsError> {
+    let s = m.value_of(name).unwrap();
+    match u64::from_str(s) {
+        Ok(v) => {
+            Ok(v)
+        },
+        Err(e) => {
+            Err(SettingsError::new(format!("invalid upload limit '{}': {}", s, e)))
+        },
+    }
+}
+
+fn quota_policy(m: &ArgMatches) -> Result<QuotaPolicy, SettingsError> {
+    let mut policy = QuotaPolicy::default();
+    policy.default.bytes = quota_limit(m, "quota_bytes")?;
+    policy.default.objects = quota_limit(m, "quota_objects")?;
+    if let Some(v) = m.value_of("quota_file") {
+        let s = match read_to_string(v) {
+            Ok(v) => {
+                v
+            },
+            Err(e) => {
+                return Err(SettingsError::new(format!("cannot read quota file {:?}: {}", v, e)));
+            },
+        };
+        if let Err(e) = policy.parse_overrides(&s) {
+            return Err(SettingsError::new(format!("invalid quota file {:?}: {}", v, e)));
+        }
+    }
+    Ok(policy)
+}
+
+fn read_pem(p: &Path) -> Result<Vec<u8>, SettingsError> {
+    match read(p) {
+        Ok(v) => {
+            Ok(v)
+        },
+        Err(e) => {
+            Err(SettingsError::new(format!("cannot read TLS file {:?}: {}", p, e)))
+        },
+    }
+}
+
+
+impl Settings {
+    /// Resolve settings from the process arguments and environment.
+    ///
+    /// Help and version requests are handled by printing and exiting the process.
+    pub fn from_args() -> Result<Settings, SettingsError> {
+        Settings::from_args_from(std::env::args_os())
+    }
+
+    pub fn from_args_from<I, T>(args: I) -> Result<Settings, SettingsError>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        match app().get_matches_from_safe(args) {
+            Ok(v) => {
+                Settings::from_matches(&v)
+            },
+            Err(e) => {
+                match e.kind {
+                    ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => {
+                        e.exit();
+                    },
+                    _ => {
+                        Err(SettingsError::new(e.message))
+                    },
+                }
+            },
+        }
+    }
+
+    fn from_matches(m: &ArgMatches) -> Result<Settings, SettingsError> {
+        let host_str = m.value_of("host").unwrap();
+        let host = match IpAddr::from_str(host_str) {
+            Ok(v) => {
+                v
+            },
+            Err(e) => {
+                return Err(SettingsError::new(format!("invalid host address '{}': {}", host_str, e)));
+            },
+        };
+
+        let port_str = m.value_of("port").unwrap();
+        let port = match u16::from_str(port_str) {
+            Ok(v) => {
+                v
+            },
+            Err(e) => {
+                return Err(SettingsError::new(format!("invalid port '{}': {}", port_str, e)));
+            },
+        };
+
+        let dir = PathBuf::from(m.value_of("datadir").unwrap());
+        if !dir.is_dir() {
+            return Err(SettingsError::new(format!("data directory {:?} does not exist or is not a directory", dir)));
+        }
+
+        let layout = match m.value_of("layout") {
+            Some(v) => {
+                Some(Layout::from_str(v).map_err(SettingsError::new)?)
+            },
+            None => {
+                None
+            },
+        };
+
+        let loglevel_str = m.value_of("loglevel").unwrap();
+        let loglevel = match LevelFilter::from_str(loglevel_str) {
+            Ok(v) => {
+                v
+            },
+            Err(_) => {
+                return Err(SettingsError::new(format!("invalid log level '{}'", loglevel_str)));
+            },
+        };
+
+        let workers_str = m.value_of("workers").unwrap();
+        let workers = match usize::from_str(workers_str) {
+            Ok(v) if v > 0 => {
+                v
+            },
+            _ => {
+                return Err(SettingsError::new(format!("invalid worker count '{}'", workers_str)));
+            },
+        };
+
+        let queue_str = m.value_of("queue").unwrap();
+        let queue = match usize::from_str(queue_str) {
+            Ok(v) => {
+                v
+            },
+            Err(e) => {
+                return Err(SettingsError::new(format!("invalid queue limit '{}': {}", queue_str, e)));
+            },
+        };
+
+        let quota = quota_policy(m)?;
+        let s3 = s3_settings(m)?;
+        let upload = UploadLimits {
+            anonymous: upload_limit(m, "max_upload")?,
+            authenticated: upload_limit(m, "max_upload_auth")?,
+        };
+
+        let mut aliases = vec!();
+        for v in m.values_of("alias_hash").into_iter().flatten() {
+            aliases.push(HashAlgo::from_str(v).map_err(SettingsError::new)?);
+        }
+
+        let mode = match m.subcommand() {
+            ("gc", Some(v)) => {
+                Mode::Gc(gc_settings(v)?)
+            },
+            ("fsck", Some(v)) => {
+                Mode::Fsck(FsckSettings {
+                    quarantine: v.is_present("quarantine"),
+                    json: v.is_present("json"),
+                })
+            },
+            ("migrate", Some(_)) => {
+                Mode::Migrate
+            },
+            _ => {
+                Mode::Serve
+            },
+        };
+
+        let tls_cert = tls_path(m, "tls_cert")?;
+        let tls_key = tls_path(m, "tls_key")?;
+
+        Ok(Settings {
+            host,
+            port,
+            dir,
+            layout,
+            loglevel,
+            tls_cert,
+            tls_key,
+            workers,
+            queue,
+            quota,
+            upload,
+            aliases,
+            legacy_auth: flag(m, "legacy_auth", std::env::var("WALA_LEGACY_AUTH").ok().as_deref()),
+            trust_store: m.value_of("trust_store").map(PathBuf::from),
+            s3,
+            mode,
+        })
+    }
+
+    /// Load the TLS certificate and key, if configured.
+    pub fn ssl_config(&self) -> Result<Option<SslConfig>, SettingsError> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => {
+                let cfg = SslConfig {
+                    certificate: read_pem(cert)?,
+                    private_key: read_pem(key)?,
+                };
+                Ok(Some(cfg))
+            },
+            _ => {
+                Ok(None)
+            },
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.host, self.port)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        app,
+        flag,
+        Mode,
+        Settings,
+    };
+    use crate::hash::HashAlgo;
+    use crate::store::fs::Layout;
+    use std::fs::write;
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use tempfile::tempdir;
+    use log::LevelFilter;
+
+    #[test]
+    fn test_settings_args() {
+        let d = tempdir().unwrap();
+        let dir = d.path().to_str().unwrap();
+        let args = vec!("wala", "--host", "127.0.0.1", "-p", "8002", "-d", dir, "-l", "debug");
+        let settings = Settings::from_args_from(args).unwrap();
+        assert_eq!(settings.addr().to_string(), "127.0.0.1:8002");
+        assert_eq!(settings.dir, d.path());
+        assert_eq!(settings.loglevel, LevelFilter::Debug);
+        assert_eq!(settings.workers, 4);
+        assert_eq!(settings.upload.for_request(false), Some(10485760));
+    }
+
+    #[test]
+    fn test_settings_upload() {
+        let d = tempdir().unwrap();
+        let dir = d.path().to_str().unwrap();
+        let args = vec!("wala", "-d", dir, "--max-upload", "1024", "--max-upload-auth", "0");
+        let settings = Settings::from_args_from(args).unwrap();
+        assert_eq!(settings.upload.for_request(false), Some(1024));
+        assert_eq!(settings.upload.for_request(true), None);
+
+        let args = vec!("wala", "-d", dir, "--max-upload", "1k");
+        assert!(Settings::from_args_from(args).is_err());
+    }
+
+    #[test]
+    fn test_settings_gc() {
+        let d = tempdir().unwrap();
+        let dir = d.path().to_str().unwrap();
+
+        let args = vec!("wala", "-d", dir);
+        let settings = Settings::from_args_from(args).unwrap();
+        assert!(matches!(settings.mode, Mode::Serve));
+
+        let args = vec!("wala", "gc", "-d", dir, "--grace", "60", "-n");
+        let settings = Settings::from_args_from(args).unwrap();
+        assert_eq!(settings.dir, d.path());
+        match settings.mode {
+            Mode::Gc(v) => {
+                assert_eq!(v.grace, Duration::from_secs(60));
+                assert!(v.dry_run);
+                assert!(v.pins.is_none());
+                assert!(!v.keep_history);
+            },
+            _ => {
+                panic!("expected gc mode");
+            },
+        }
+
+        let args = vec!("wala", "gc", "-d", dir, "--keep-history");
+        match Settings::from_args_from(args).unwrap().mode {
+            Mode::Gc(v) => {
+                assert!(v.keep_history);
+            },
+            _ => {
+                panic!("expected gc mode");
+            },
+        }
+
+        let args = vec!("wala", "gc", "-d", dir, "--pins", "/nonexistent");
+        assert!(Settings::from_args_from(args).is_err());
+    }
+
+    #[test]
+    fn test_settings_fsck() {
+        let d = tempdir().unwrap();
+        let dir = d.path().to_str().unwrap();
+
+        let args = vec!("wala", "fsck", "-d", dir, "--json");
+        let settings = Settings::from_args_from(args).unwrap();
+        match settings.mode {
+            Mode::Fsck(v) => {
+                assert!(v.json);
+                assert!(!v.quarantine);
+            },
+            _ => {
+                panic!("expected fsck mode");
+            },
+        }
+    }
+
+    #[test]
+    fn test_settings_alias() {
+        let d = tempdir().unwrap();
+        let dir = d.path().to_str().unwrap();
+
+        let args = vec!("wala", "-d", dir);
+        let settings = Settings::from_args_from(args).unwrap();
+        assert!(settings.aliases.is_empty());
+
+        let args = vec!("wala", "-d", dir, "--alias-hash", "keccak256", "--alias-hash", "blake3");
+        let settings = Settings::from_args_from(args).unwrap();
+        assert_eq!(settings.aliases, vec!(HashAlgo::Keccak256, HashAlgo::Blake3));
+
+        let args = vec!("wala", "-d", dir, "--alias-hash", "md5");
+        assert!(Settings::from_args_from(args).is_err());
+    }
+
+    #[test]
+    fn test_settings_legacy_auth() {
+        let d = tempdir().unwrap();
+        let dir = d.path().to_str().unwrap();
+
+        let args = vec!("wala", "-d", dir, "--legacy-auth");
+        let settings = Settings::from_args_from(args).unwrap();
+        assert!(settings.legacy_auth);
+
+        let m = app().get_matches_from(vec!("wala", "-d", dir));
+        assert!(!flag(&m, "legacy_auth", None));
+        for (v, expected) in [("1", true), ("true", true), ("TRUE", true), ("0", false), ("false", false), ("", false)] {
+            assert_eq!(flag(&m, "legacy_auth", Some(v)), expected, "WALA_LEGACY_AUTH={:?}", v);
+        }
+
+        // the flag wins over the environment
+        let m = app().get_matches_from(vec!("wala", "-d", dir, "--legacy-auth"));
+        assert!(flag(&m, "legacy_auth", Some("0")));
+    }
+
+    #[test]
+    fn test_settings_trust_store() {
+        let d = tempdir().unwrap();
+        let dir = d.path().to_str().unwrap();
+
+        let args = vec!("wala", "-d", dir);
+        let settings = Settings::from_args_from(args).unwrap();
+        assert!(settings.trust_store.is_none());
+
+        let args = vec!("wala", "-d", dir, "--trust-store", "/etc/wala/trusted");
+        let settings = Settings::from_args_from(args).unwrap();
+        assert_eq!(settings.trust_store, Some(PathBuf::from("/etc/wala/trusted")));
+    }
+
+    #[test]
+    fn test_settings_layout() {
+        let d = tempdir().unwrap();
+        let dir = d.path().to_str().unwrap();
+
+        let args = vec!("wala", "-d", dir);
+        let settings = Settings::from_args_from(args).unwrap();
+        assert_eq!(settings.layout, None);
+
+        let args = vec!("wala", "-d", dir, "--layout", "sharded");
+        let settings = Settings::from_args_from(args).unwrap();
+        assert_eq!(settings.layout, Some(Layout::Sharded));
+
+        let args = vec!("wala", "migrate", "-d", dir);
+        let settings = Settings::from_args_from(args).unwrap();
+        assert!(matches!(settings.mode, Mode::Migrate));
+
+        let args = vec!("wala", "-d", dir, "--layout", "deep");
+        assert!(Settings::from_args_from(args).is_err());
+    }
+
+    #[test]
+    fn test_settings_invalid() {
+        let d = tempdir().unwrap();
+        let dir = d.path().to_str().unwrap();
+
+        let args = vec!("wala", "--host", "localhost", "-d", dir);
+        assert!(Settings::from_args_from(args).is_err());
+
+        let args = vec!("wala", "--port", "65536", "-d", dir);
+        assert!(Settings::from_args_from(args).is_err());
+
+        let args = vec!("wala", "-d", dir, "-l", "loud");
+        assert!(Settings::from_args_from(args).is_err());
+
+        let missing = d.path().join("nonexistent");
+        let args = vec!("wala", "-d", missing.to_str().unwrap());
+        assert!(Settings::from_args_from(args).is_err());
+
+        let args = vec!("wala", "-d", dir, "--workers", "0");
+        assert!(Settings::from_args_from(args).is_err());
+
+        let args = vec!("wala", "-d", dir, "--tls-cert", dir);
+        assert!(Settings::from_args_from(args).is_err());
+
+        let args = vec!("wala", "-d", dir, "--quota-bytes", "-1");
+        assert!(Settings::from_args_from(args).is_err());
+
+        let args = vec!("wala", "-d", dir, "--s3-endpoint", "http://localhost:9000");
+        assert!(Settings::from_args_from(args).is_err());
+    }
+
+    #[cfg(feature = "s3")]
+    #[test]
+    fn test_settings_s3() {
+        let d = tempdir().unwrap();
+        let dir = d.path().to_str().unwrap();
+        let args = vec!("wala", "-d", dir, "--s3-endpoint", "http://localhost:9000", "--s3-bucket", "wala", "--s3-access-key", "foo", "--s3-secret-key", "bar");
+        let settings = Settings::from_args_from(args).unwrap();
+        let s3 = settings.s3.unwrap();
+        assert_eq!(s3.bucket, "wala");
+        assert_eq!(s3.region, "us-east-1");
+
+        let args = vec!("wala", "-d", dir, "--s3-endpoint", "localhost:9000", "--s3-bucket", "wala", "--s3-access-key", "foo", "--s3-secret-key", "bar");
+        assert!(Settings::from_args_from(args).is_err());
+    }
+
+    #[test]
+    fn test_settings_quota() {
+        let d = tempdir().unwrap();
+        let dir = d.path().to_str().unwrap();
+        let p = d.path().join("quota");
+        write(&p, "666f6f 100 -\n").unwrap();
+
+        let args = vec!("wala", "-d", dir, "--quota-bytes", "10", "--quota-file", p.to_str().unwrap());
+        let settings = Settings::from_args_from(args).unwrap();
+        assert_eq!(settings.quota.limits_for(b"bar").bytes, Some(10));
+        assert_eq!(settings.quota.limits_for(b"bar").objects, None);
+        assert_eq!(settings.quota.limits_for(b"foo").bytes, Some(100));
+
+        write(&p, "666f6f 100\n").unwrap();
+        let args = vec!("wala", "-d", dir, "--quota-file", p.to_str().unwrap());
+        assert!(Settings::from_args_from(args).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use std::io::Read;
+use crate::auth::{
+    AuthSpec,
+    AuthError,
+    AuthResult,
+};
+use ed25519_dalek::{
+    PublicKey,
+    Signature,
+};
+use base64;
+
+use log::{debug, error};
+
+
+fn check_sig(public_key: &PublicKey, signature_data: &[u8], mut message: impl Read) -> bool {
+    let signature = match Signature::from_bytes(signature_data) {
+        Ok(v) => {
+            v
+        },
+        Err(_) => {
+            return false;
+        },
+    };
+    let mut data: Vec<u8> = vec!();
+    if message.read_to_end(&mut data).is_err() {
+        return false;
+    }
+    public_key.verify_strict(&data, &signature).is_ok()
+}
+
+/// Verify a signature over the request body made with a raw Ed25519 key.
+///
+/// Both the 32 byte public key and the 64 byte signature are base64 encoded. The public key
+/// itself is the identity.
+pub fn auth_check(auth: &AuthSpec, data: impl Read, _data_length: usize) -> Result<AuthResult, AuthError> {
+    if auth.method != "ed25519" {
+        return Err(AuthError{});
+    }
+
+    let key_data = match base64::decode(&auth.key) {
+        Ok(v) => {
+            v
+        },
+        Err(_) => {
+            return Err(AuthError{});
+        }
+    };
+
+    let sig_data = match base64::decode(&auth.signature) {
+        Ok(v) => {
+            v
+        },
+        Err(_) => {
+            return Err(AuthError{});
+        }
+    };
+
+    let key = match PublicKey::from_bytes(&key_data) {
+        Ok(v) => {
+            v
+        },
+        Err(_) => {
+            return Err(AuthError{});
+        },
+    };
+
+    if !check_sig(&key, &sig_data, data) {
+        error!("invalid ed25519 signature for {:?}", hex::encode(key.as_bytes()));
+        return Err(AuthError{});
+    }
+    debug!("found valid ed25519 key {:?}", hex::encode(key.as_bytes()));
+
+    let res = AuthResult {
+        identity: key.as_bytes().to_vec(),
+        error: false,
+        signature: auth.signature.clone(),
+    };
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::auth_check;
+    use super::AuthSpec;
+    use crate::auth::signed_message;
+    use crate::hash::HashAlgo;
+    use std::str::FromStr;
+
+    // public key of RFC 8032 section 7.1 TEST 1
+    const KEY_HEX: &str = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+
+    fn auth_spec(key_hex: &str, sig_hex: &str) -> AuthSpec {
+        let key_base64 = base64::encode(hex::decode(key_hex).unwrap());
+        let sig_base64 = base64::encode(hex::decode(sig_hex).unwrap());
+        let auth_spec_str = format!("PUBSIG ed25519:{}:{}", key_base64, sig_base64);
+        AuthSpec::from_str(&auth_spec_str).unwrap()
+    }
+
+    #[test]
+    fn test_ed25519_rfc8032() {
+        let sig_empty_hex = "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b";
+        let auth_spec = auth_spec(KEY_HEX, sig_empty_hex);
+
+        let data = b"";
+        match auth_check(&auth_spec, &data[..], 0) {
+            Ok(v) => {
+                assert_eq!(hex::encode(&v.identity), KEY_HEX);
+            },
+            Err(e) => {
+                panic!("{}", e);
+            },
+        }
+    }
+
+    #[test]
+    fn test_ed25519_auth() {
+        let sig_foo_hex = "07817da934b8984b6f77ebb96da4bdf7a4cd1a2313067b0b4299c4ee0ab6acdc0f5236f995936826635c373dec6729388212318352dc3b125b697a30a8d16202";
+        let auth_spec = auth_spec(KEY_HEX, sig_foo_hex);
+
+        let data = b"foo";
+        match auth_check(&auth_spec, &data[..], 0) {
+            Ok(v) => {
+                assert_eq!(hex::encode(&v.identity), KEY_HEX);
+            },
+            Err(e) => {
+                panic!("{}", e);
+            },
+        }
+
+        let data = b"bar";
+        assert!(auth_check(&auth_spec, &data[..], 0).is_err());
+
+        let mut sig_foo = hex::decode(sig_foo_hex).unwrap();
+        sig_foo[0] ^= 0x01;
+        let auth_spec = self::auth_spec(KEY_HEX, &hex::encode(&sig_foo));
+        let data = b"foo";
+        assert!(auth_check(&auth_spec, &data[..], 0).is_err());
+
+        let auth_spec = AuthSpec::from_str(&format!("PUBSIG pgp:{}:{}", base64::encode(hex::decode(KEY_HEX).unwrap()), base64::encode(hex::decode(sig_foo_hex).unwrap()))).unwrap();
+        assert!(auth_check(&auth_spec, &data[..], 0).is_err());
+    }
+
+    #[test]
+    fn test_ed25519_auth_bound() {
+        // signature over the message for PUT of "bar" to the sha256 reference for the key "foo"
+        // with sequence 1
+        let sig_hex = "34227c02d71233a049c675c7e8bc4f7ccf1217f079bb9944bc26167e70f5e4936b2f814903a181b3f1c478748e92cfb0147412bc6aac2920775a9e068f9d7f0e";
+        let auth_spec = auth_spec(KEY_HEX, sig_hex);
+
+        let message = signed_message("PUT", "foo", HashAlgo::Sha256, Some(1), &b"bar"[..]).unwrap();
+        assert!(auth_check(&auth_spec, &message[..], 0).is_ok());
+
+        let cases = [
+            ("PUT", "baz", HashAlgo::Sha256, Some(1)),
+            ("DELETE", "foo", HashAlgo::Sha256, Some(1)),
+            ("PUT", "foo", HashAlgo::Keccak256, Some(1)),
+            ("PUT", "foo", HashAlgo::Sha256, Some(2)),
+            ("PUT", "foo", HashAlgo::Sha256, None),
+        ];
+        for (method, key, pointer_hash, sequence) in cases {
+            let message = signed_message(method, key, pointer_hash, sequence, &b"bar"[..]).unwrap();
+            assert!(auth_check(&auth_spec, &message[..], 0).is_err());
+        }
+        let data = b"bar";
+        assert!(auth_check(&auth_spec, &data[..], 0).is_err());
+    }
+}
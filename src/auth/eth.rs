@@ -0,0 +1,151 @@
+use std::io::Read;
+use crate::auth::{
+    AuthSpec,
+    AuthError,
+    AuthResult,
+};
+use crate::hash::HashAlgo;
+use k256::ecdsa::recoverable::Signature;
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use k256::FieldBytes;
+
+use log::{debug, error};
+
+/// Prefix of messages signed with `personal_sign`, as defined by EIP-191.
+const PERSONAL_SIGN_PREFIX: &[u8] = b"\x19Ethereum Signed Message:\n";
+
+/// Offset wallets add to the recovery id of `personal_sign` signatures.
+const RECOVERY_ID_OFFSET: u8 = 27;
+
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    hex::decode(s.strip_prefix("0x").unwrap_or(s)).ok()
+}
+
+/// Digest signed by `personal_sign` for the given message.
+fn personal_digest(message: &[u8]) -> Vec<u8> {
+    let mut h = HashAlgo::Keccak256.hasher();
+    h.update(PERSONAL_SIGN_PREFIX);
+    h.update(message.len().to_string().as_bytes());
+    h.update(message);
+    h.finalize()
+}
+
+/// Address of the key that made a `personal_sign` signature over a message.
+fn recover_address(signature_data: &[u8], message: &[u8]) -> Option<Vec<u8>> {
+    let mut signature_data = signature_data.to_vec();
+    match signature_data.get_mut(64) {
+        Some(v) if *v >= RECOVERY_ID_OFFSET => {
+            *v -= RECOVERY_ID_OFFSET;
+        },
+        _ => {},
+    };
+    let signature = Signature::try_from(signature_data.as_slice()).ok()?;
+    let digest = personal_digest(message);
+    let key = signature.recover_verify_key_from_digest_bytes(FieldBytes::from_slice(&digest)).ok()?;
+    let point = key.to_encoded_point(false);
+    let address = HashAlgo::Keccak256.digest(&point.as_bytes()[1..]);
+    Some(address[12..].to_vec())
+}
+
+/// Recover the signer of an EIP-191 `personal_sign` signature over the request body.
+///
+/// The key is the hex address the client claims to sign with, and must match the recovered
+/// one. The 20 byte address is the identity.
+pub fn auth_check(auth: &AuthSpec, mut data: impl Read, _data_length: usize) -> Result<AuthResult, AuthError> {
+    if auth.method != "eth" {
+        return Err(AuthError{});
+    }
+
+    let claimed = match decode_hex(&auth.key) {
+        Some(v) => {
+            v
+        },
+        None => {
+            return Err(AuthError{});
+        },
+    };
+
+    let sig_data = match decode_hex(&auth.signature) {
+        Some(v) => {
+            v
+        },
+        None => {
+            return Err(AuthError{});
+        },
+    };
+
+    let mut message: Vec<u8> = vec!();
+    if data.read_to_end(&mut message).is_err() {
+        return Err(AuthError{});
+    }
+
+    let address = match recover_address(&sig_data, &message) {
+        Some(v) => {
+            v
+        },
+        None => {
+            error!("invalid eth signature for {:?}", hex::encode(&claimed));
+            return Err(AuthError{});
+        },
+    };
+    if address != claimed {
+        error!("eth signature for {:?} made by {:?}", hex::encode(&claimed), hex::encode(&address));
+        return Err(AuthError{});
+    }
+    debug!("found valid eth address {:?}", hex::encode(&address));
+
+    let res = AuthResult {
+        identity: address,
+        error: false,
+        signature: auth.signature.clone(),
+    };
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::auth_check;
+    use super::AuthSpec;
+    use std::str::FromStr;
+
+    // signer with private key keccak256("wala")
+    const ADDRESS_HEX: &str = "85c341c666e351b4801e20b433409fbb72b567ea";
+    const SIG_FOO_HEX: &str = "f973a0b87062c389d125d8199e803b832b6ac6bf7867a4f6cd87506060fc4c5804bc0fcfde09574562184a8f1db1f6265ad9e6ab47adffda4c1da6afcf9617ac1b";
+
+    #[test]
+    fn test_eth_auth() {
+        let auth_spec = AuthSpec::from_str(&format!("PUBSIG eth:0x{}:0x{}", ADDRESS_HEX, SIG_FOO_HEX)).unwrap();
+
+        let data = b"foo";
+        match auth_check(&auth_spec, &data[..], 0) {
+            Ok(v) => {
+                assert_eq!(hex::encode(&v.identity), ADDRESS_HEX);
+            },
+            Err(e) => {
+                panic!("{}", e);
+            },
+        }
+
+        let sig_empty_hex = "0fd109be4a9e07d64737926c7e2d8d7afa63ec5991d126b8abe7919d3c62a7be00ce4e944243494bc526f965d20639a100e133afcd8e36f0e224c53b485adaca1c";
+        let auth_spec = AuthSpec::from_str(&format!("PUBSIG eth:{}:{}", ADDRESS_HEX.to_uppercase(), sig_empty_hex)).unwrap();
+        let data = b"";
+        assert!(auth_check(&auth_spec, &data[..], 0).is_ok());
+    }
+
+    #[test]
+    fn test_eth_auth_mismatch() {
+        // address of private key 1
+        let auth_spec = AuthSpec::from_str(&format!("PUBSIG eth:0x7e5f4552091a69125d5dfcb7b8c2659029395bdf:0x{}", SIG_FOO_HEX)).unwrap();
+        let data = b"foo";
+        assert!(auth_check(&auth_spec, &data[..], 0).is_err());
+
+        let auth_spec = AuthSpec::from_str(&format!("PUBSIG eth:{}:{}", ADDRESS_HEX, SIG_FOO_HEX)).unwrap();
+        let data = b"bar";
+        assert!(auth_check(&auth_spec, &data[..], 0).is_err());
+
+        let auth_spec = AuthSpec::from_str(&format!("PUBSIG eth:{}:{}", ADDRESS_HEX, &SIG_FOO_HEX[..128])).unwrap();
+        let data = b"foo";
+        assert!(auth_check(&auth_spec, &data[..], 0).is_err());
+    }
+}
@@ -18,6 +18,7 @@ pub fn auth_check(auth: &AuthSpec, data: impl Read, data_length: usize) -> Resul
     let res = AuthResult{
         identity: auth.key.as_bytes().to_vec(),
         error: false,
+        signature: auth.signature.clone(),
     };
     Ok(res)
 }
@@ -1,10 +1,22 @@
 use std::str::FromStr;
 use std::error::Error;
 use std::fmt;
+use std::io;
+use std::io::{
+    copy as io_copy,
+    Read,
+};
+
+use crate::hash::HashAlgo;
+
+/// Version tag starting every signed message, changed whenever its format changes.
+const SIGNED_MESSAGE_TAG: &str = "wala-pubsig-3";
 
 pub struct AuthResult {
     pub identity: Vec<u8>,
     pub error: bool,
+    /// Signature as presented by the client, kept as proof of the update it authorized.
+    pub signature: String,
 }
 
 pub struct AuthSpec {
@@ -118,9 +130,59 @@ impl Error for AuthError {
     }
 }
 
+/// Message a `PUBSIG` signature is made over for a request.
+///
+/// Six lines separated by newlines, without a trailing newline: the version tag, the HTTP
+/// method, the key as it appears in the request path without the leading slash, the algorithm
+/// the reference is derived with, the decimal sequence of the update or `-` if it has none, and
+/// the hex SHA-256 digest of the body. Covering method, key and algorithm keeps a signature from
+/// being replayed against any other reference, covering the sequence keeps it from being
+/// replayed against the same one later.
+pub fn signed_message(method: &str, key: &str, pointer_hash: HashAlgo, sequence: Option<u64>, mut body: impl Read) -> io::Result<Vec<u8>> {
+    let mut h = HashAlgo::Sha256.hasher();
+    io_copy(&mut body, &mut h)?;
+    let sequence = match sequence {
+        Some(v) => {
+            v.to_string()
+        },
+        None => {
+            String::from("-")
+        },
+    };
+    let s = format!("{}\n{}\n{}\n{}\n{}\n{}", SIGNED_MESSAGE_TAG, method, key, pointer_hash, sequence, hex::encode(h.finalize()));
+    Ok(s.into_bytes())
+}
+
 
 #[cfg(feature = "dev")]
 pub mod mock;
 
 #[cfg(feature = "pgpauth")]
 pub mod pgp;
+
+#[cfg(feature = "ed25519auth")]
+pub mod ed25519;
+
+#[cfg(feature = "ethauth")]
+pub mod eth;
+
+
+#[cfg(test)]
+mod tests {
+    use super::signed_message;
+    use crate::hash::HashAlgo;
+
+    #[test]
+    fn test_signed_message() {
+        let r = signed_message("PUT", "foo", HashAlgo::Sha256, Some(1), &b"bar"[..]).unwrap();
+        assert_eq!(r, b"wala-pubsig-3\nPUT\nfoo\nsha256\n1\nfcde2b2edba56bf408601fb721fe9b5c338d10ee429ea04fae5511b68fbf8fb9");
+
+        let r = signed_message("DELETE", "foo", HashAlgo::Keccak256, None, &b""[..]).unwrap();
+        assert_eq!(r, b"wala-pubsig-3\nDELETE\nfoo\nkeccak256\n-\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+
+        // the same update of the reference derived with the other algorithm needs its own signature
+        let sha256 = signed_message("PUT", "foo", HashAlgo::Sha256, Some(1), &b"bar"[..]).unwrap();
+        let keccak256 = signed_message("PUT", "foo", HashAlgo::Keccak256, Some(1), &b"bar"[..]).unwrap();
+        assert_ne!(sha256, keccak256);
+    }
+}
@@ -1,4 +1,7 @@
-use std::io::Read;
+use std::io::{
+    Cursor,
+    Read,
+};
 use crate::auth::{
     AuthSpec,
     AuthError,
@@ -102,6 +105,44 @@ fn check_sig_bundle(public_key: &PublicKey, signature_data: Vec<u8>, mut message
     false
 }
 
+/// Fingerprints of the primary keys in an OpenPGP keyring, the identities `auth_check` gives
+/// for them.
+///
+/// The keyring is either a single ASCII armored block or binary, as exported by `gpg --export`.
+pub fn keyring_fingerprints(data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let keys = match data.starts_with(b"-----") {
+        true => {
+            match SignedPublicKey::from_armor_many(Cursor::new(data)) {
+                Ok((v, _)) => {
+                    v
+                },
+                Err(e) => {
+                    return Err(format!("invalid armored keyring: {}", e));
+                },
+            }
+        },
+        false => {
+            SignedPublicKey::from_bytes_many(data)
+        },
+    };
+    let mut r = vec!();
+    for v in keys {
+        match v {
+            Ok(v) => {
+                debug!("found key {:?} in keyring", hex::encode(v.primary_key.fingerprint()));
+                r.push(v.primary_key.fingerprint());
+            },
+            Err(e) => {
+                return Err(format!("invalid key in keyring: {}", e));
+            },
+        };
+    }
+    if r.is_empty() {
+        return Err(String::from("no keys in keyring"));
+    }
+    Ok(r)
+}
+
 pub fn auth_check(auth: &AuthSpec, data: impl Read, data_length: usize) -> Result<AuthResult, AuthError> {
     if auth.method != "pgp" {
         return Err(AuthError{});
@@ -157,6 +198,7 @@ pub fn auth_check(auth: &AuthSpec, data: impl Read, data_length: usize) -> Resul
     let res = AuthResult {
         identity: key.fingerprint(),
         error: false,
+        signature: auth.signature.clone(),
     };
     Ok(res)
 }
@@ -0,0 +1,123 @@
+use crate::hash::parse_name;
+
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Multibase prefix of lowercase unpadded base32.
+const MULTIBASE_BASE32: char = 'b';
+
+const CID_VERSION: u8 = 0x01;
+
+/// Multicodec of content served as is, without any IPLD structure.
+const CODEC_RAW: u8 = 0x55;
+
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut r = String::new();
+    let mut buf: u32 = 0;
+    let mut bits = 0;
+    for v in data {
+        buf = (buf << 8) | *v as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            r.push(BASE32_ALPHABET[((buf >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        r.push(BASE32_ALPHABET[((buf << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    r
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut r = vec!();
+    let mut buf: u32 = 0;
+    let mut bits = 0;
+    for c in s.bytes() {
+        let v = BASE32_ALPHABET.iter().position(|v| *v == c.to_ascii_lowercase())?;
+        buf = (buf << 5) | v as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            r.push((buf >> bits) as u8);
+        }
+    }
+    // leftover bits are padding, and must be zero
+    if bits >= 5 || buf & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    Some(r)
+}
+
+/// Express a record name as a base32 CIDv1 with the raw codec.
+///
+/// IPFS clients can verify content fetched by such a CID against it, as the CID embeds the
+/// multihash of the content.
+pub fn to_cid(name: &[u8]) -> Option<String> {
+    let (algo, digest) = parse_name(name)?;
+    let mut v = vec!(CID_VERSION, CODEC_RAW, algo.code(), digest.len() as u8);
+    v.extend_from_slice(digest);
+    Some(format!("{}{}", MULTIBASE_BASE32, base32_encode(&v)))
+}
+
+/// Name of the record a base32 CIDv1 with the raw codec refers to.
+pub fn from_cid(s: &str) -> Option<Vec<u8>> {
+    let mut chars = s.chars();
+    if !chars.next()?.eq_ignore_ascii_case(&MULTIBASE_BASE32) {
+        return None;
+    }
+    let v = base32_decode(chars.as_str())?;
+    match v.as_slice() {
+        [CID_VERSION, CODEC_RAW, multihash @ ..] => {
+            let (algo, digest) = parse_name(multihash)?;
+            if multihash.len() != digest.len() + 2 {
+                return None;
+            }
+            Some(algo.name(digest))
+        },
+        _ => {
+            None
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        base32_decode,
+        base32_encode,
+        from_cid,
+        to_cid,
+    };
+    use crate::hash::HashAlgo;
+
+    #[test]
+    fn test_base32() {
+        // RFC 4648 test vectors, lowercase and unpadded
+        for (data, encoded) in [("", ""), ("f", "my"), ("fo", "mzxq"), ("foo", "mzxw6"), ("foob", "mzxw6yq"), ("fooba", "mzxw6ytb"), ("foobar", "mzxw6ytboi")] {
+            assert_eq!(base32_encode(data.as_bytes()), encoded);
+            assert_eq!(base32_decode(encoded).unwrap(), data.as_bytes());
+        }
+        assert!(base32_decode("mzxw6z").is_none());
+        assert!(base32_decode("mzxw61").is_none());
+    }
+
+    #[test]
+    fn test_cid() {
+        // as computed by `ipfs add --cid-version 1 --raw-leaves` for "foo"
+        let digest = hex::decode("2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae").unwrap();
+        let cid = "bafkreibme22gw2h7y2h7tg2fhqotaqjucnbc24deqo72b6mkl2egezxhvy";
+        assert_eq!(to_cid(&digest).unwrap(), cid);
+        assert_eq!(from_cid(cid).unwrap(), digest);
+        assert_eq!(from_cid(&cid.to_uppercase()).unwrap(), digest);
+
+        let name = HashAlgo::Blake3.name(&[0x11; 32]);
+        assert_eq!(from_cid(&to_cid(&name).unwrap()).unwrap(), name);
+
+        assert!(from_cid("zafkreibme22gw2h7y2h7tg2fhqotaqjucnbc24deqo72b6mkl2egezxhvy").is_none());
+        // dag-pb rather than raw
+        assert!(from_cid("bafybeibme22gw2h7y2h7tg2fhqotaqjucnbc24deqo72b6mkl2egezxhvy").is_none());
+        assert!(from_cid(&cid[..cid.len() - 2]).is_none());
+    }
+}
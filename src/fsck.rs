@@ -0,0 +1,309 @@
+use std::fs::{
+    create_dir_all,
+    read_link,
+    rename,
+    File,
+};
+use std::io;
+use std::io::copy as io_copy;
+use std::path::Path;
+
+use log::{debug, info, warn};
+
+use crate::hash::{
+    parse_name,
+    HashAlgo,
+};
+use crate::meta::sidecar_path;
+use crate::store::fs::FsStore;
+
+/// Directory in the store root that corrupt records are moved to.
+pub const QUARANTINE_DIR: &str = ".quarantine";
+
+
+/// Outcome of an integrity check.
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    /// Records whose content hashes to their name.
+    pub ok: usize,
+    /// Records whose content does not hash to their name.
+    pub corrupt: Vec<String>,
+    /// Mutable references whose record does not exist.
+    pub dangling: Vec<String>,
+    /// Mutable references pointing at something other than a record in the store.
+    pub foreign: Vec<String>,
+    /// Records and mutable references that could not be read.
+    pub unreadable: Vec<String>,
+    /// Corrupt records moved to the quarantine directory.
+    pub quarantined: usize,
+}
+
+fn json_list(v: &[String]) -> String {
+    let items: Vec<String> = v.iter().map(|v| format!("\"{}\"", v)).collect();
+    format!("[{}]", items.join(","))
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.corrupt.is_empty() && self.dangling.is_empty() && self.foreign.is_empty() && self.unreadable.is_empty()
+    }
+
+    /// Summary as a single line JSON object.
+    ///
+    /// Only entries with hex names are checked, so no escaping is needed.
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"ok\":{},\"corrupt\":{},\"dangling\":{},\"foreign\":{},\"unreadable\":{},\"quarantined\":{},\"clean\":{}}}",
+            self.ok,
+            json_list(&self.corrupt),
+            json_list(&self.dangling),
+            json_list(&self.foreign),
+            json_list(&self.unreadable),
+            self.quarantined,
+            self.is_clean(),
+            )
+    }
+}
+
+/// Name a file's content would be stored under, with the given algorithm.
+fn hash_file(path: &Path, algo: HashAlgo) -> io::Result<String> {
+    let mut f = File::open(path)?;
+    let mut h = algo.hasher();
+    io_copy(&mut f, &mut h)?;
+    Ok(hex::encode(algo.name(&h.finalize())))
+}
+
+fn quarantine(store: &FsStore, name: &str) -> io::Result<()> {
+    let dir = store.path().join(QUARANTINE_DIR);
+    create_dir_all(&dir)?;
+    let path = store.entry_path(name);
+    let meta_path = sidecar_path(&path);
+    if meta_path.exists() {
+        rename(&meta_path, sidecar_path(&dir.join(name)))?;
+    }
+    rename(&path, dir.join(name))?;
+    File::open(&dir)?.sync_all()
+}
+
+/// Rehash every immutable record and check that every mutable reference points at one.
+///
+/// Corrupt records are moved to the quarantine directory if `quarantine` is set, after which
+/// references to them are reported as dangling. Entries that are not named by hex, such as
+/// temporary links left by interrupted updates, are skipped.
+pub fn fsck(store: &FsStore, quarantine_corrupt: bool) -> io::Result<FsckReport> {
+    let mut report = FsckReport::default();
+    let mut links = vec!();
+
+    for entry in store.entries()? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let typ = entry.file_type()?;
+        if typ.is_symlink() {
+            if hex::decode(&name).is_err() {
+                debug!("skipping link {}, not a mutable reference name", name);
+                continue;
+            }
+            links.push((name, entry.path()));
+            continue;
+        }
+        if !typ.is_file() {
+            continue;
+        }
+        let algo = match hex::decode(&name).ok().as_deref().and_then(parse_name) {
+            Some((v, _)) => {
+                v
+            },
+            None => {
+                debug!("skipping {}, not a record name", name);
+                continue;
+            },
+        };
+        let digest = match hash_file(&entry.path(), algo) {
+            Ok(v) => {
+                v
+            },
+            Err(e) => {
+                warn!("cannot read record {}: {}", name, e);
+                report.unreadable.push(name);
+                continue;
+            },
+        };
+        if digest == name {
+            debug!("record {} ok", name);
+            report.ok += 1;
+            continue;
+        }
+        warn!("record {} has content hashing to {}", name, digest);
+        if quarantine_corrupt {
+            quarantine(store, &name)?;
+            info!("record {} moved to quarantine", name);
+            report.quarantined += 1;
+        }
+        report.corrupt.push(name);
+    }
+
+    for (name, path) in links {
+        let target = match read_link(&path) {
+            Ok(v) => {
+                v
+            },
+            Err(e) => {
+                warn!("cannot read mutable reference {}: {}", name, e);
+                report.unreadable.push(name);
+                continue;
+            },
+        };
+        let target_name = target.file_name().map(|v| v.to_string_lossy().to_string());
+        let target_path = path.parent().unwrap_or(Path::new("")).join(&target);
+        let expected = target_name.as_deref().filter(|v| hex::decode(v).is_ok()).map(|v| store.entry_path(v));
+        let is_record = match expected {
+            Some(v) => {
+                match (target_path.parent().map(|v| v.canonicalize()), v.parent().map(|v| v.canonicalize())) {
+                    (Some(Ok(a)), Some(Ok(b))) => {
+                        a == b
+                    },
+                    // the directory the record would be in does not exist
+                    _ => {
+                        true
+                    },
+                }
+            },
+            None => {
+                false
+            },
+        };
+        if !is_record {
+            warn!("mutable reference {} has foreign target {:?}", name, target);
+            report.foreign.push(name);
+        } else if !target_path.is_file() {
+            warn!("mutable reference {} points at missing record {:?}", name, target);
+            report.dangling.push(name);
+        }
+    }
+
+    Ok(report)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        fsck,
+        QUARANTINE_DIR,
+    };
+    use crate::hash::HashAlgo;
+    use crate::meta::ContentMeta;
+    use crate::record::{
+        put_immutable,
+        put_mutable,
+    };
+    use crate::store::fs::{
+        FsStore,
+        Layout,
+    };
+    use std::fs::{
+        remove_file,
+        set_permissions,
+        write,
+        File,
+        Permissions,
+    };
+    use std::os::unix::fs::{
+        symlink,
+        PermissionsExt,
+    };
+    use tempfile::tempdir;
+
+    const FOO_DIGEST: &str = "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae";
+    const BAR_DIGEST: &str = "fcde2b2edba56bf408601fb721fe9b5c338d10ee429ea04fae5511b68fbf8fb9";
+
+    #[test]
+    fn test_fsck_corrupt() {
+        let d = tempdir().unwrap();
+        let store = FsStore::with_layout(d.path(), Layout::Sharded);
+        let mut meta = ContentMeta::default();
+        meta.set_type("text/plain");
+        put_mutable(&store, b"foobar".to_vec(), &b"foo"[..], 3, HashAlgo::Sha256, &[], &meta, "", 1).unwrap();
+        put_immutable(&store, &b"foo"[..], 3, HashAlgo::Blake3, &[], &meta).unwrap();
+
+        let r = fsck(&store, false).unwrap();
+        assert!(r.is_clean());
+        assert_eq!(r.ok, 2);
+
+        write(store.entry_path(FOO_DIGEST), b"fop").unwrap();
+        let r = fsck(&store, false).unwrap();
+        assert_eq!(r.corrupt, vec!(FOO_DIGEST.to_string()));
+        assert_eq!(r.quarantined, 0);
+        assert!(store.entry_path(FOO_DIGEST).is_file());
+
+        let r = fsck(&store, true).unwrap();
+        assert_eq!(r.quarantined, 1);
+        assert!(!store.entry_path(FOO_DIGEST).exists());
+        assert!(d.path().join(QUARANTINE_DIR).join(FOO_DIGEST).is_file());
+        assert!(d.path().join(QUARANTINE_DIR).join(format!("{}.meta", FOO_DIGEST)).is_file());
+        assert_eq!(r.dangling, vec!(hex::encode(b"foobar")));
+
+        let r = fsck(&store, true).unwrap();
+        assert!(r.corrupt.is_empty());
+        assert_eq!(r.dangling.len(), 1);
+    }
+
+    #[test]
+    fn test_fsck_links() {
+        let d = tempdir().unwrap();
+        let store = FsStore::new(d.path());
+        put_mutable(&store, b"foo".to_vec(), &b"foo"[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default(), "", 1).unwrap();
+        put_mutable(&store, b"bar".to_vec(), &b"bar"[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default(), "", 1).unwrap();
+        remove_file(d.path().join(BAR_DIGEST)).unwrap();
+        symlink("/etc/passwd", d.path().join(hex::encode(b"baz"))).unwrap();
+        symlink(format!("/{}", FOO_DIGEST), d.path().join(hex::encode(b"xyzzy"))).unwrap();
+
+        let r = fsck(&store, false).unwrap();
+        assert!(!r.is_clean());
+        assert_eq!(r.ok, 1);
+        assert_eq!(r.dangling, vec!(hex::encode(b"bar")));
+        let mut foreign = r.foreign.clone();
+        foreign.sort();
+        assert_eq!(foreign, vec!(hex::encode(b"baz"), hex::encode(b"xyzzy")));
+
+        let json = r.to_json();
+        assert!(json.starts_with("{\"ok\":1,\"corrupt\":[],"));
+        assert!(json.contains(&format!("\"dangling\":[\"{}\"]", hex::encode(b"bar"))));
+        assert!(json.ends_with("\"unreadable\":[],\"quarantined\":0,\"clean\":false}"));
+    }
+
+    #[test]
+    fn test_fsck_skip() {
+        let d = tempdir().unwrap();
+        let store = FsStore::new(d.path());
+        put_mutable(&store, b"foo".to_vec(), &b"foo"[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default(), "", 1).unwrap();
+
+        // leftover link of an interrupted update, and a name that would break the JSON summary
+        symlink(d.path().join(FOO_DIGEST), d.path().join(format!(".{}.1.0.tmp", hex::encode(b"foo")))).unwrap();
+        symlink("/etc/passwd", d.path().join("foo\"bar")).unwrap();
+
+        let r = fsck(&store, false).unwrap();
+        assert!(r.is_clean());
+        assert_eq!(r.ok, 1);
+        assert!(r.to_json().ends_with("\"foreign\":[],\"unreadable\":[],\"quarantined\":0,\"clean\":true}"));
+    }
+
+    #[test]
+    fn test_fsck_unreadable() {
+        let d = tempdir().unwrap();
+        let store = FsStore::new(d.path());
+        put_immutable(&store, &b"foo"[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default()).unwrap();
+        put_immutable(&store, &b"bar"[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default()).unwrap();
+        let path = d.path().join(BAR_DIGEST);
+        set_permissions(&path, Permissions::from_mode(0o000)).unwrap();
+        if File::open(&path).is_ok() {
+            // permissions are not enforced, as when running as root
+            return;
+        }
+
+        // one unreadable record does not stop the others from being checked
+        let r = fsck(&store, false).unwrap();
+        assert_eq!(r.ok, 1);
+        assert_eq!(r.unreadable, vec!(BAR_DIGEST.to_string()));
+        assert!(!r.is_clean());
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+use std::fs::{
+    read_link,
+    read_to_string,
+    remove_file,
+};
+use std::io;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::time::{
+    Duration,
+    SystemTime,
+};
+
+use log::{debug, info};
+
+use crate::hash::{
+    parse_name,
+    HashAlgo,
+};
+use crate::history::{
+    parse as history_parse,
+    HISTORY_SUFFIX,
+};
+use crate::meta::sidecar_path;
+use crate::store::fs::FsStore;
+
+
+/// Outcome of a garbage collection run.
+#[derive(Debug, Default)]
+pub struct GcReport {
+    /// Records that are still referenced, or too young to be collected.
+    pub kept: usize,
+    /// Unreferenced records with their sizes, removed unless the run was a dry run.
+    pub removed: Vec<(Vec<u8>, u64)>,
+    /// Leftover staging files and temporary links from interrupted uploads and updates.
+    pub stale: usize,
+}
+
+impl GcReport {
+    pub fn removed_bytes(&self) -> u64 {
+        self.removed.iter().map(|v| v.1).sum()
+    }
+}
+
+/// Read pinned digests, one hex digest per line.
+///
+/// Empty lines and lines starting with `#` are skipped.
+pub fn read_pins(path: &Path) -> io::Result<Vec<Vec<u8>>> {
+    let s = read_to_string(path)?;
+    let mut r = vec!();
+    for l in s.lines() {
+        let l = l.trim();
+        if l.is_empty() || l.starts_with('#') {
+            continue;
+        }
+        match hex::decode(l) {
+            Ok(v) => {
+                r.push(v);
+            },
+            Err(e) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid pin {:?}: {}", l, e)));
+            },
+        }
+    }
+    Ok(r)
+}
+
+fn is_older(modified: io::Result<SystemTime>, grace: Duration, now: SystemTime) -> bool {
+    match modified {
+        Ok(v) => {
+            match now.duration_since(v) {
+                Ok(age) => {
+                    age >= grace
+                },
+                Err(_) => {
+                    false
+                },
+            }
+        },
+        Err(_) => {
+            false
+        },
+    }
+}
+
+/// Whether a link is an alias, named by the multihash of a record in another algorithm.
+///
+/// Mutable references are always 32 bytes, which reads as a bare SHA-256 digest.
+fn is_alias(name: &str) -> bool {
+    match hex::decode(name).ok().as_deref().and_then(parse_name) {
+        Some((algo, _)) => {
+            algo != HashAlgo::Sha256
+        },
+        None => {
+            false
+        },
+    }
+}
+
+/// Remove immutable records that no mutable reference points to and that are not pinned.
+///
+/// Records that mutable references pointed to before are removed too, unless `keep_history` is
+/// set, which keeps every record named in a revision log. Only records last written more than
+/// `grace` ago are removed, which protects uploads whose mutable reference has not been linked
+/// yet. Aliases do not keep records, and are removed along with them.
+pub fn gc(store: &FsStore, pins: &[Vec<u8>], grace: Duration, keep_history: bool, dry_run: bool) -> io::Result<GcReport> {
+    let mut reachable: HashSet<Vec<u8>> = pins.iter().cloned().collect();
+    let mut records = vec!();
+    let mut staged = vec!();
+    let mut aliases: HashMap<Vec<u8>, Vec<PathBuf>> = HashMap::new();
+
+    // mark
+    for entry in store.entries()? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let typ = entry.file_type()?;
+        if name.starts_with('.') && name.ends_with(".tmp") {
+            // temporary link of an interrupted mutable reference update
+            staged.push(entry);
+            continue;
+        }
+        if typ.is_symlink() {
+            let target = read_link(entry.path())?;
+            let target_name = target.file_name().map(|v| v.to_string_lossy().to_string());
+            match target_name.map(hex::decode) {
+                Some(Ok(v)) if is_alias(&name) => {
+                    debug!("alias {} for {}", name, hex::encode(&v));
+                    aliases.entry(v).or_default().push(entry.path());
+                },
+                Some(Ok(v)) => {
+                    debug!("mutable reference {} keeps {}", name, hex::encode(&v));
+                    reachable.insert(v);
+                },
+                _ => {
+                    debug!("ignoring link {} with foreign target {:?}", name, target);
+                },
+            }
+        } else if typ.is_file() {
+            if name.starts_with(".tmp") {
+                staged.push(entry);
+                continue;
+            }
+            if name.ends_with(HISTORY_SUFFIX) {
+                if !keep_history {
+                    continue;
+                }
+                for v in history_parse(&read_to_string(entry.path())?) {
+                    if !v.digest.is_empty() {
+                        debug!("revision log {} keeps {}", name, hex::encode(&v.digest));
+                        reachable.insert(v.digest);
+                    }
+                }
+                continue;
+            }
+            if let Ok(v) = hex::decode(&name) {
+                records.push((v, entry));
+            }
+        }
+    }
+
+    // sweep
+    let now = SystemTime::now();
+    let mut report = GcReport::default();
+    for (digest, entry) in records {
+        let metadata = entry.metadata()?;
+        if reachable.contains(&digest) || !is_older(metadata.modified(), grace, now) {
+            report.kept += 1;
+            continue;
+        }
+        if !dry_run {
+            remove_file(entry.path())?;
+            let meta_path = sidecar_path(&entry.path());
+            if meta_path.exists() {
+                remove_file(meta_path)?;
+            }
+            for v in aliases.get(&digest).into_iter().flatten() {
+                remove_file(v)?;
+            }
+        }
+        info!("unreferenced record {} ({} bytes){}", hex::encode(&digest), metadata.len(), if dry_run { ", dry run" } else { " removed" });
+        report.removed.push((digest, metadata.len()));
+    }
+    for entry in staged {
+        if !is_older(entry.metadata()?.modified(), grace, now) {
+            continue;
+        }
+        if !dry_run {
+            remove_file(entry.path())?;
+        }
+        report.stale += 1;
+    }
+
+    Ok(report)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        gc,
+        read_pins,
+        GcReport,
+    };
+    use crate::hash::HashAlgo;
+    use crate::meta::ContentMeta;
+    use crate::record::{
+        delete_mutable,
+        get_revision,
+        put_immutable,
+        put_mutable,
+        RequestResultType,
+    };
+    use crate::store::fs::{
+        FsStore,
+        Layout,
+    };
+    use std::fs::write;
+    use std::os::unix::fs::symlink;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    const FOO_DIGEST: &str = "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae";
+    const BAR_DIGEST: &str = "fcde2b2edba56bf408601fb721fe9b5c338d10ee429ea04fae5511b68fbf8fb9";
+    const BAZ_DIGEST: &str = "baa5a0964d3320fbc0c6a922140453c8513ea24ab8fd0577034804a967248096";
+    const QUX_DIGEST: &str = "21f58d27f827d295ffcd860c65045685e3baf1ad4506caa0140113b316647534";
+
+    /// Digests of the removed records, in hex and sorted, as entries are read in any order.
+    fn removed(r: &GcReport) -> Vec<String> {
+        let mut v: Vec<String> = r.removed.iter().map(|v| hex::encode(&v.0)).collect();
+        v.sort();
+        v
+    }
+
+    #[test]
+    fn test_gc() {
+        let d = tempdir().unwrap();
+        let store = FsStore::new(d.path());
+        let mut meta = ContentMeta::default();
+        meta.set_type("text/plain");
+        put_mutable(&store, b"foobar".to_vec(), &b"foo"[..], 3, HashAlgo::Sha256, &[], &meta, "", 1).unwrap();
+        put_mutable(&store, b"foobar".to_vec(), &b"bar"[..], 3, HashAlgo::Sha256, &[], &meta, "", 2).unwrap();
+        put_immutable(&store, &b"baz"[..], 3, HashAlgo::Sha256, &[], &meta).unwrap();
+        put_immutable(&store, &b"qux"[..], 3, HashAlgo::Sha256, &[], &meta).unwrap();
+
+        let pins = vec!(hex::decode(BAZ_DIGEST).unwrap());
+
+        // everything is within the grace period
+        let r = gc(&store, &pins, Duration::from_secs(3600), false, false).unwrap();
+        assert_eq!(r.kept, 4);
+        assert!(r.removed.is_empty());
+
+        // the old target of the mutable reference is orphaned
+        let r = gc(&store, &pins, Duration::ZERO, false, true).unwrap();
+        assert_eq!(removed(&r), vec!(QUX_DIGEST, FOO_DIGEST));
+        assert!(d.path().join(QUX_DIGEST).is_file());
+        assert!(d.path().join(FOO_DIGEST).is_file());
+
+        let r = gc(&store, &pins, Duration::ZERO, false, false).unwrap();
+        assert_eq!(r.kept, 2);
+        assert_eq!(r.removed_bytes(), 6);
+        assert!(!d.path().join(QUX_DIGEST).exists());
+        assert!(!d.path().join(format!("{}.meta", QUX_DIGEST)).exists());
+        assert!(!d.path().join(FOO_DIGEST).exists());
+        assert!(d.path().join(BAR_DIGEST).is_file());
+        assert!(d.path().join(BAZ_DIGEST).is_file());
+
+        // the collected revision stays in the log, but its content is gone
+        assert_eq!(get_revision(&store, b"foobar", 0).err().unwrap().typ, RequestResultType::Gone);
+        assert!(get_revision(&store, b"foobar", 1).is_ok());
+
+        let r = gc(&store, &[], Duration::ZERO, false, false).unwrap();
+        assert_eq!(removed(&r), vec!(BAZ_DIGEST));
+        assert!(!d.path().join(BAZ_DIGEST).exists());
+    }
+
+    #[test]
+    fn test_gc_keep_history() {
+        let d = tempdir().unwrap();
+        let store = FsStore::new(d.path());
+        let meta = ContentMeta::default();
+        put_mutable(&store, b"foobar".to_vec(), &b"foo"[..], 3, HashAlgo::Sha256, &[], &meta, "", 1).unwrap();
+        put_mutable(&store, b"foobar".to_vec(), &b"bar"[..], 3, HashAlgo::Sha256, &[], &meta, "", 2).unwrap();
+        put_immutable(&store, &b"qux"[..], 3, HashAlgo::Sha256, &[], &meta).unwrap();
+
+        // past revisions are kept by the revision log
+        let r = gc(&store, &[], Duration::ZERO, true, false).unwrap();
+        assert_eq!(r.kept, 2);
+        assert_eq!(removed(&r), vec!(QUX_DIGEST));
+        assert!(d.path().join(FOO_DIGEST).is_file());
+        assert!(get_revision(&store, b"foobar", 0).is_ok());
+
+        // also after the reference is deleted
+        delete_mutable(&store, b"foobar".to_vec(), "", 3).unwrap();
+        let r = gc(&store, &[], Duration::ZERO, true, false).unwrap();
+        assert_eq!(r.kept, 2);
+        assert!(r.removed.is_empty());
+
+        let r = gc(&store, &[], Duration::ZERO, false, false).unwrap();
+        assert_eq!(removed(&r), vec!(FOO_DIGEST, BAR_DIGEST));
+        assert_eq!(get_revision(&store, b"foobar", 1).err().unwrap().typ, RequestResultType::Gone);
+    }
+
+    #[test]
+    fn test_gc_stale() {
+        let d = tempdir().unwrap();
+        let store = FsStore::new(d.path());
+        write(d.path().join(".tmpabcdef"), b"foo").unwrap();
+
+        let r = gc(&store, &[], Duration::from_secs(3600), false, false).unwrap();
+        assert_eq!(r.stale, 0);
+
+        let r = gc(&store, &[], Duration::ZERO, false, false).unwrap();
+        assert_eq!(r.stale, 1);
+        assert!(!d.path().join(".tmpabcdef").exists());
+
+        // a temporary link left by an interrupted update is removed, and keeps nothing
+        put_immutable(&store, &b"foo"[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default()).unwrap();
+        let link = d.path().join(format!(".{}.1.0.tmp", hex::encode(b"foobar")));
+        symlink(d.path().join(FOO_DIGEST), &link).unwrap();
+
+        let r = gc(&store, &[], Duration::ZERO, false, false).unwrap();
+        assert_eq!(r.stale, 1);
+        assert_eq!(r.removed, vec!((hex::decode(FOO_DIGEST).unwrap(), 3)));
+        assert!(link.symlink_metadata().is_err());
+    }
+
+    #[test]
+    fn test_gc_sharded() {
+        let d = tempdir().unwrap();
+        let store = FsStore::with_layout(d.path(), Layout::Sharded);
+        let meta = ContentMeta::default();
+        put_mutable(&store, b"foobar".to_vec(), &b"foo"[..], 3, HashAlgo::Sha256, &[], &meta, "", 1).unwrap();
+        put_mutable(&store, b"foobar".to_vec(), &b"bar"[..], 3, HashAlgo::Sha256, &[], &meta, "", 2).unwrap();
+        put_immutable(&store, &b"qux"[..], 3, HashAlgo::Sha256, &[], &meta).unwrap();
+
+        let r = gc(&store, &[], Duration::ZERO, false, false).unwrap();
+        assert_eq!(r.kept, 1);
+        assert_eq!(removed(&r), vec!(QUX_DIGEST, FOO_DIGEST));
+        assert!(!store.entry_path(QUX_DIGEST).exists());
+        assert!(!store.entry_path(FOO_DIGEST).exists());
+        assert!(store.entry_path(BAR_DIGEST).is_file());
+    }
+
+    #[test]
+    fn test_gc_alias() {
+        let d = tempdir().unwrap();
+        let store = FsStore::new(d.path());
+        put_immutable(&store, &b"foo"[..], 3, HashAlgo::Sha256, &[HashAlgo::Keccak256], &ContentMeta::default()).unwrap();
+        let alias = d.path().join("1b2041b1a0649752af1b28b3dc29a1556eee781e4a4c3a1f7f53f90fa834de098c4d");
+        assert!(alias.is_symlink());
+
+        // aliases do not keep records
+        let r = gc(&store, &[], Duration::ZERO, false, false).unwrap();
+        assert_eq!(r.removed, vec!((hex::decode(FOO_DIGEST).unwrap(), 3)));
+        assert!(alias.symlink_metadata().is_err());
+    }
+
+    #[test]
+    fn test_gc_pins() {
+        let d = tempdir().unwrap();
+        let p = d.path().join("pins");
+        write(&p, format!("# pinned\n{}\n\n", FOO_DIGEST)).unwrap();
+        assert_eq!(read_pins(&p).unwrap(), vec!(hex::decode(FOO_DIGEST).unwrap()));
+
+        write(&p, "foo\n").unwrap();
+        assert!(read_pins(&p).is_err());
+    }
+}
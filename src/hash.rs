@@ -0,0 +1,301 @@
+use std::fmt;
+use std::io;
+use std::io::{
+    Read,
+    Write,
+};
+use std::str::FromStr;
+
+use sha2::{
+    Digest,
+    Sha256,
+    Sha512,
+};
+use sha3::Keccak256;
+
+
+/// Content hash algorithms records can be addressed by.
+///
+/// SHA-256 records are named by the bare digest, as they always have been. Records hashed with
+/// any other algorithm are named by the multihash of their digest, a one byte algorithm code and
+/// a one byte length followed by the digest itself, so that names never collide across
+/// algorithms.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum HashAlgo {
+    #[default]
+    Sha256,
+    Sha512,
+    Blake3,
+    /// The pre-standard SHA-3 variant used by Ethereum.
+    Keccak256,
+}
+
+const ALGOS: [HashAlgo; 4] = [
+    HashAlgo::Sha256,
+    HashAlgo::Sha512,
+    HashAlgo::Blake3,
+    HashAlgo::Keccak256,
+];
+
+impl HashAlgo {
+    /// Multihash code of the algorithm.
+    pub fn code(&self) -> u8 {
+        match self {
+            HashAlgo::Sha256 => {
+                0x12
+            },
+            HashAlgo::Sha512 => {
+                0x13
+            },
+            HashAlgo::Blake3 => {
+                0x1e
+            },
+            HashAlgo::Keccak256 => {
+                0x1b
+            },
+        }
+    }
+
+    pub fn digest_len(&self) -> usize {
+        match self {
+            HashAlgo::Sha512 => {
+                64
+            },
+            _ => {
+                32
+            },
+        }
+    }
+
+    pub fn hasher(&self) -> Hasher {
+        match self {
+            HashAlgo::Sha256 => {
+                Hasher::Sha256(Sha256::new())
+            },
+            HashAlgo::Sha512 => {
+                Hasher::Sha512(Sha512::new())
+            },
+            HashAlgo::Blake3 => {
+                Hasher::Blake3(Box::default())
+            },
+            HashAlgo::Keccak256 => {
+                Hasher::Keccak256(Keccak256::new())
+            },
+        }
+    }
+
+    /// Whether mutable references can be derived with this algorithm.
+    ///
+    /// References are always 32 bytes, see [`crate::record::ResourceKey::with_algo`].
+    pub fn is_pointer_algo(&self) -> bool {
+        matches!(self, HashAlgo::Sha256 | HashAlgo::Keccak256)
+    }
+
+    pub fn digest(&self, data: &[u8]) -> Vec<u8> {
+        let mut h = self.hasher();
+        h.update(data);
+        h.finalize()
+    }
+
+    /// Name a record with the given digest is stored under.
+    pub fn name(&self, digest: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlgo::Sha256 => {
+                digest.to_vec()
+            },
+            _ => {
+                let mut r = vec!(self.code(), digest.len() as u8);
+                r.extend_from_slice(digest);
+                r
+            },
+        }
+    }
+}
+
+impl FromStr for HashAlgo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<HashAlgo, String> {
+        match ALGOS.iter().find(|v| v.to_string().eq_ignore_ascii_case(s)) {
+            Some(v) => {
+                Ok(*v)
+            },
+            None => {
+                Err(format!("unsupported hash algorithm '{}'", s))
+            },
+        }
+    }
+}
+
+impl fmt::Display for HashAlgo {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashAlgo::Sha256 => {
+                fmt.write_str("sha256")
+            },
+            HashAlgo::Sha512 => {
+                fmt.write_str("sha512")
+            },
+            HashAlgo::Blake3 => {
+                fmt.write_str("blake3")
+            },
+            HashAlgo::Keccak256 => {
+                fmt.write_str("keccak256")
+            },
+        }
+    }
+}
+
+/// Split a record name into the algorithm and the digest it was computed with.
+///
+/// SHA-256 digests are accepted both bare and as multihash.
+pub fn parse_name(name: &[u8]) -> Option<(HashAlgo, &[u8])> {
+    if name.len() == HashAlgo::Sha256.digest_len() {
+        return Some((HashAlgo::Sha256, name));
+    }
+    let (code, len, digest) = match name {
+        [code, len, digest @ ..] => {
+            (*code, *len as usize, digest)
+        },
+        _ => {
+            return None;
+        },
+    };
+    let algo = ALGOS.iter().find(|v| v.code() == code)?;
+    if len != algo.digest_len() || digest.len() != len {
+        return None;
+    }
+    Some((*algo, digest))
+}
+
+/// Running digest computation for one of the supported algorithms.
+pub enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Blake3(Box<blake3::Hasher>),
+    Keccak256(Keccak256),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(h) => {
+                h.update(data);
+            },
+            Hasher::Sha512(h) => {
+                h.update(data);
+            },
+            Hasher::Blake3(h) => {
+                h.update(data);
+            },
+            Hasher::Keccak256(h) => {
+                h.update(data);
+            },
+        }
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(h) => {
+                h.finalize().to_vec()
+            },
+            Hasher::Sha512(h) => {
+                h.finalize().to_vec()
+            },
+            Hasher::Blake3(h) => {
+                h.finalize().as_bytes().to_vec()
+            },
+            Hasher::Keccak256(h) => {
+                h.finalize().to_vec()
+            },
+        }
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reader computing digests of everything read through it.
+pub struct HashingReader<R> {
+    inner: R,
+    hashers: Vec<(HashAlgo, Hasher)>,
+    size: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R, algos: &[HashAlgo]) -> HashingReader<R> {
+        HashingReader {
+            inner,
+            hashers: algos.iter().map(|v| (*v, v.hasher())).collect(),
+            size: 0,
+        }
+    }
+
+    /// Number of bytes read so far.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn finalize(self) -> Vec<(HashAlgo, Vec<u8>)> {
+        self.hashers.into_iter().map(|(algo, h)| (algo, h.finalize())).collect()
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        for (_, h) in self.hashers.iter_mut() {
+            h.update(&buf[..n]);
+        }
+        self.size += n as u64;
+        Ok(n)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_name,
+        HashAlgo,
+    };
+    use std::str::FromStr;
+
+    #[test]
+    fn test_hash_name() {
+        for (algo, digest) in [
+            (HashAlgo::Sha256, "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae"),
+            (HashAlgo::Sha512, "f7fbba6e0636f890e56fbbf3283e524c6fa3204ae298382d624741d0dc6638326e282c41be5e4254d8820772c5518a2c5a8c0c7f7eda19594a7eb539453e1ed7"),
+            (HashAlgo::Blake3, "04e0bb39f30b1a3feb89f536c93be15055482df748674b00d26e5a75777702e9"),
+            (HashAlgo::Keccak256, "41b1a0649752af1b28b3dc29a1556eee781e4a4c3a1f7f53f90fa834de098c4d"),
+            ] {
+            let mut h = algo.hasher();
+            h.update(b"foo");
+            let v = h.finalize();
+            assert_eq!(hex::encode(&v), digest);
+
+            let name = algo.name(&v);
+            assert_eq!(parse_name(&name), Some((algo, v.as_slice())));
+            assert_eq!(HashAlgo::from_str(&algo.to_string()), Ok(algo));
+        }
+
+        let name = HashAlgo::Blake3.name(&[0; 32]);
+        assert_eq!(hex::encode(&name[..2]), "1e20");
+        let sha256_multihash = hex::decode(format!("1220{}", "00".repeat(32))).unwrap();
+        assert_eq!(parse_name(&sha256_multihash), Some((HashAlgo::Sha256, &[0u8; 32][..])));
+        assert_eq!(parse_name(&name[..33]), None);
+        assert!(HashAlgo::from_str("md5").is_err());
+        assert_eq!(HashAlgo::from_str("SHA512"), Ok(HashAlgo::Sha512));
+
+        assert!(HashAlgo::Keccak256.is_pointer_algo());
+        assert!(!HashAlgo::Sha512.is_pointer_algo());
+    }
+}
@@ -0,0 +1,254 @@
+use std::fs::{
+    read_to_string,
+    OpenOptions,
+};
+use std::io;
+use std::io::Write;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::time::{
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+use log::error;
+
+pub const HISTORY_SUFFIX: &str = ".log";
+
+
+/// A past target of a mutable reference.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Revision {
+    pub digest: Vec<u8>,
+    /// Seconds since the unix epoch at which the revision was published.
+    pub timestamp: u64,
+    /// Signature that authorized the revision, as presented by the client.
+    pub signature: String,
+    /// Sequence number signed along with the revision, 0 for revisions that predate them.
+    pub sequence: u64,
+    /// Placeholder for a log entry that could not be parsed, kept so later indices do not shift.
+    pub corrupt: bool,
+}
+
+impl Revision {
+    pub fn new(digest: Vec<u8>, signature: &str, sequence: u64) -> Revision {
+        let timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(v) => {
+                v.as_secs()
+            },
+            Err(_) => {
+                0
+            },
+        };
+        Revision {
+            digest,
+            timestamp,
+            signature: signature.to_string(),
+            sequence,
+            corrupt: false,
+        }
+    }
+
+    fn corrupt() -> Revision {
+        Revision {
+            digest: vec!(),
+            timestamp: 0,
+            signature: String::new(),
+            sequence: 0,
+            corrupt: true,
+        }
+    }
+
+    /// A revision without a digest marks the deletion of the mutable reference.
+    pub fn is_tombstone(&self) -> bool {
+        self.digest.is_empty() && !self.corrupt
+    }
+
+    pub fn to_line(&self) -> String {
+        let signature = match self.signature.is_empty() {
+            true => {
+                "-"
+            },
+            false => {
+                self.signature.as_str()
+            },
+        };
+        let digest = match self.is_tombstone() {
+            true => {
+                String::from("-")
+            },
+            false => {
+                hex::encode(&self.digest)
+            },
+        };
+        format!("{} {} {} {}\n", digest, self.timestamp, signature, self.sequence)
+    }
+
+    fn from_line(s: &str) -> Option<Revision> {
+        let mut fields = s.split(' ');
+        let digest = match fields.next()? {
+            "-" => {
+                vec!()
+            },
+            v => {
+                hex::decode(v).ok()?
+            },
+        };
+        let timestamp = fields.next()?.parse().ok()?;
+        let signature = match fields.next()? {
+            "-" => {
+                String::new()
+            },
+            v => {
+                v.to_string()
+            },
+        };
+        // logs written before sequences were introduced have no sequence field
+        let sequence = match fields.next() {
+            Some(v) => {
+                v.parse().ok()?
+            },
+            None => {
+                0
+            },
+        };
+        Some(Revision {
+            digest,
+            timestamp,
+            signature,
+            sequence,
+            corrupt: false,
+        })
+    }
+}
+
+/// Location of the revision log for the mutable reference at the given path.
+pub fn history_path(link_path: &Path) -> PathBuf {
+    let mut s = link_path.as_os_str().to_os_string();
+    s.push(HISTORY_SUFFIX);
+    PathBuf::from(s)
+}
+
+/// Append a revision to the log of a mutable reference.
+///
+/// Entries are never rewritten, the index of a revision is its position in the log.
+pub fn append(link_path: &Path, revision: &Revision) -> io::Result<()> {
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(history_path(link_path))?;
+    f.write_all(revision.to_line().as_bytes())?;
+    f.sync_all()
+}
+
+/// Parse a revision log.
+///
+/// Corrupt entries are kept as placeholders, see [`Revision::corrupt`], so that every revision
+/// keeps its index.
+pub fn parse(s: &str) -> Vec<Revision> {
+    let mut r = vec!();
+    for l in s.lines() {
+        match Revision::from_line(l) {
+            Some(v) => {
+                r.push(v);
+            },
+            None => {
+                error!("corrupt history entry {:?}", l);
+                r.push(Revision::corrupt());
+            },
+        }
+    }
+    r
+}
+
+/// List all revisions of a mutable reference, oldest first.
+pub fn list(link_path: &Path) -> io::Result<Vec<Revision>> {
+    let s = read_to_string(history_path(link_path))?;
+    Ok(parse(&s))
+}
+
+/// Render revisions for a listing response, one `index digest timestamp signature sequence` per
+/// line, or `index corrupt` for entries that could not be parsed.
+pub fn render(revisions: &[Revision]) -> String {
+    let mut s = String::new();
+    for (i, v) in revisions.iter().enumerate() {
+        match v.corrupt {
+            true => {
+                s.push_str(&format!("{} corrupt\n", i));
+            },
+            false => {
+                s.push_str(&format!("{} {}", i, v.to_line()));
+            },
+        };
+    }
+    s
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        append,
+        list,
+        parse,
+        render,
+        Revision,
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_history() {
+        let d = tempdir().unwrap();
+        let p = d.path().join("deadbeef");
+
+        assert!(list(&p).is_err());
+
+        let first = Revision {
+            digest: vec!(0x66, 0x6f, 0x6f),
+            timestamp: 1,
+            signature: String::from("c2ln"),
+            sequence: 1,
+            corrupt: false,
+        };
+        let second = Revision {
+            digest: vec!(0x62, 0x61, 0x72),
+            timestamp: 2,
+            signature: String::new(),
+            sequence: 2,
+            corrupt: false,
+        };
+        append(&p, &first).unwrap();
+        append(&p, &second).unwrap();
+
+        let third = Revision {
+            digest: vec!(),
+            timestamp: 3,
+            signature: String::from("c2ln"),
+            sequence: 5,
+            corrupt: false,
+        };
+        append(&p, &third).unwrap();
+
+        let r = list(&p).unwrap();
+        assert!(r[2].is_tombstone());
+        assert_eq!(r, vec!(first, second, third));
+        assert_eq!(render(&r), "0 666f6f 1 c2ln 1\n1 626172 2 - 2\n2 - 3 c2ln 5\n");
+
+        let r = parse("666f6f 1 c2ln\n");
+        assert_eq!(r[0].sequence, 0);
+    }
+
+    #[test]
+    fn test_history_corrupt() {
+        let r = parse("666f6f 1 - 1\n66 6f 6f\n626172 3 - 2\n- 4 - 3\n");
+        assert_eq!(r.len(), 4);
+        assert_eq!(r[0].digest, b"foo".to_vec());
+        assert!(r[1].corrupt);
+        assert!(!r[1].is_tombstone());
+        assert_eq!(r[2].digest, b"bar".to_vec());
+        assert!(r[3].is_tombstone());
+        assert_eq!(render(&r), "0 666f6f 1 - 1\n1 corrupt\n2 626172 3 - 2\n3 - 4 - 3\n");
+    }
+}
@@ -7,37 +7,104 @@ use tiny_http::{
     Header,
     Method,
 };
-use std::net::{Ipv4Addr, SocketAddrV4};
 use std::str::FromStr;
-use std::path::{PathBuf, Path};
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
 use std::fs::File;
 use std::error::Error;
 use std::fmt;
+use std::io;
 use std::io::{
     copy as io_copy,
     Read,
     Seek,
+    SeekFrom,
     empty,
 };
 
 use env_logger;
 
+mod arg;
+use arg::{
+    FsckSettings,
+    GcSettings,
+    UploadLimits,
+    Mode,
+    Settings,
+};
+
 mod auth;
 use auth::{
+    signed_message,
     AuthSpec,
     AuthResult,
 };
 
+mod meta;
+use meta::ContentMeta;
+
+mod cid;
+use cid::to_cid;
+
+mod hash;
+use hash::HashAlgo;
+
+mod history;
+
+mod gc;
+use gc::{
+    gc as gc_store,
+    read_pins,
+};
+
+mod fsck;
+use fsck::fsck as fsck_store;
+
 mod record;
 use record::{
+    RecordMeta,
     RequestResult,
     RequestResultType,
 };
 
 mod request;
-use request::process_method;
+use request::{
+    process_method,
+    RequestContext,
+};
 
-use log::{debug, info, error};
+mod quota;
+use quota::QuotaPolicy;
+
+mod store;
+use store::{
+    RecordReader,
+    Store,
+};
+use store::fs::{
+    migrate,
+    FsStore,
+};
+#[cfg(feature = "s3")]
+use store::s3::S3Store;
+
+mod pool;
+use pool::WorkerPool;
+
+mod trust;
+use trust::TrustStore;
+
+mod range;
+use range::{
+    parse_range,
+    if_range_match,
+    unsatisfied_range,
+    ByteRange,
+    RangeError,
+};
+
+use log::{debug, info, warn, error};
 
 use tempfile::tempfile;
 
@@ -48,6 +115,12 @@ use crate::auth::mock::auth_check as mock_auth_check;
 #[cfg(feature = "pgpauth")]
 use crate::auth::pgp::auth_check as pgp_auth_check;
 
+#[cfg(feature = "ed25519auth")]
+use crate::auth::ed25519::auth_check as ed25519_auth_check;
+
+#[cfg(feature = "ethauth")]
+use crate::auth::eth::auth_check as eth_auth_check;
+
 
 #[derive(Debug)]
 pub struct NoAuthError;
@@ -65,6 +138,196 @@ impl fmt::Display for NoAuthError {
 }
 
 
+fn etag(m: &RecordMeta) -> String {
+    format!("\"{}\"", hex::encode(&m.digest))
+}
+
+
+fn meta_headers(m: &RecordMeta) -> Vec<Header> {
+    let digest_hex = hex::encode(&m.digest);
+    let mut headers = vec!(
+        Header::from_bytes(&b"ETag"[..], etag(m).as_bytes()).unwrap(),
+        Header::from_bytes(&b"X-Wala-Digest"[..], digest_hex.as_bytes()).unwrap(),
+    );
+    if let Some(v) = to_cid(&m.digest) {
+        headers.push(Header::from_bytes(&b"X-Wala-Cid"[..], v.as_bytes()).unwrap());
+    }
+    if m.sequence > 0 {
+        headers.push(Header::from_bytes(&b"X-Wala-Sequence"[..], m.sequence.to_string().as_bytes()).unwrap());
+    }
+    if let Some(v) = &m.content.typ {
+        match Header::from_bytes(&b"Content-Type"[..], v.as_bytes()) {
+            Ok(h) => {
+                headers.push(h);
+            },
+            Err(_) => {
+                error!("invalid stored content type {:?}", v);
+            },
+        }
+    }
+    if let Some(v) = m.content.disposition() {
+        match Header::from_bytes(&b"Content-Disposition"[..], v.as_bytes()) {
+            Ok(h) => {
+                headers.push(h);
+            },
+            Err(_) => {
+                error!("invalid stored filename {:?}", v);
+            },
+        }
+    }
+    headers
+}
+
+
+fn content_meta_from_headers(headers: &[Header]) -> ContentMeta {
+    let mut meta = ContentMeta::default();
+    for h in headers {
+        if h.field.equiv("Content-Type") {
+            meta.set_type(h.value.as_str());
+        } else if h.field.equiv("Content-Disposition") {
+            meta.set_disposition(h.value.as_str());
+        }
+    }
+    meta
+}
+
+
+/// Hash algorithm requested with the `X-Wala-Hash` header, if any.
+fn hash_from_headers(headers: &[Header]) -> Result<Option<HashAlgo>, String> {
+    for h in headers {
+        if h.field.equiv("X-Wala-Hash") {
+            return HashAlgo::from_str(h.value.as_str().trim()).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+
+/// Algorithm to derive mutable references with, requested with the `X-Wala-Pointer-Hash` header,
+/// if any.
+fn pointer_hash_from_headers(headers: &[Header]) -> Result<Option<HashAlgo>, String> {
+    for h in headers {
+        if h.field.equiv("X-Wala-Pointer-Hash") {
+            let algo = HashAlgo::from_str(h.value.as_str().trim())?;
+            if !algo.is_pointer_algo() {
+                return Err(format!("mutable references cannot be derived with {}", algo));
+            }
+            return Ok(Some(algo));
+        }
+    }
+    Ok(None)
+}
+
+
+/// Sequence of a mutable reference update given with the `X-Wala-Sequence` header, if any.
+fn sequence_from_headers(headers: &[Header]) -> Result<Option<u64>, String> {
+    for h in headers {
+        if h.field.equiv("X-Wala-Sequence") {
+            let v = h.value.as_str().trim();
+            return u64::from_str(v).map(Some).map_err(|e| format!("invalid sequence '{}': {}", v, e));
+        }
+    }
+    Ok(None)
+}
+
+
+fn range_from_headers(headers: &[Header], m: &Option<RecordMeta>, size: u64) -> Option<Result<ByteRange, RangeError>> {
+    let mut range: Option<&str> = None;
+    for h in headers {
+        if h.field.equiv("If-Range") {
+            let matched = match m {
+                Some(v) => {
+                    if_range_match(h.value.as_str(), &etag(v))
+                },
+                None => {
+                    false
+                },
+            };
+            if !matched {
+                debug!("if-range precondition failed, sending full content");
+                return None;
+            }
+        } else if h.field.equiv("Range") {
+            range = Some(h.value.as_str());
+        }
+    }
+    match range {
+        Some(v) => {
+            match parse_range(v, size) {
+                Err(RangeError::Malformed) => {
+                    debug!("ignoring malformed range {}", v);
+                    None
+                },
+                r => {
+                    Some(r)
+                },
+            }
+        },
+        None => {
+            None
+        },
+    }
+}
+
+
+fn exec_file_response(req: Request, mut f: Box<dyn RecordReader>, res_status: StatusCode, m: Option<Box<RecordMeta>>, ranged: bool) {
+    let m = m.map(|v| *v);
+    let size = match f.size() {
+        Ok(v) => {
+            v
+        },
+        Err(e) => {
+            error!("cannot stat record: {}", e);
+            let _ = req.respond(Response::empty(StatusCode(500)));
+            return;
+        },
+    };
+
+    let mut headers = vec!(
+        Header::from_bytes(&b"Accept-Ranges"[..], &b"bytes"[..]).unwrap(),
+    );
+    if let Some(v) = &m {
+        headers.extend(meta_headers(v));
+    }
+
+    let range = match ranged {
+        true => {
+            range_from_headers(req.headers(), &m, size)
+        },
+        false => {
+            None
+        },
+    };
+
+    match range {
+        Some(Ok(v)) => {
+            if let Err(e) = f.seek(SeekFrom::Start(v.start)) {
+                error!("cannot seek record: {}", e);
+                let _ = req.respond(Response::empty(StatusCode(500)));
+                return;
+            }
+            headers.push(Header::from_bytes(&b"Content-Range"[..], v.content_range(size).as_bytes()).unwrap());
+            let len = v.len() as usize;
+            let res = Response::new(StatusCode(206), headers, f.take(v.len()), Some(len), None)
+                .with_chunked_threshold(usize::MAX);
+            let _ = req.respond(res);
+        },
+        Some(Err(e)) => {
+            debug!("cannot satisfy range request: {:?}", e);
+            headers.push(Header::from_bytes(&b"Content-Range"[..], unsatisfied_range(size).as_bytes()).unwrap());
+            let res = Response::new(StatusCode(416), headers, empty(), Some(0), None);
+            let _ = req.respond(res);
+        },
+        None => {
+            // send a content length instead of chunks, so that HEAD reports the size
+            let res = Response::new(res_status, headers, f, Some(size as usize), None)
+                .with_chunked_threshold(usize::MAX);
+            let _ = req.respond(res);
+        },
+    }
+}
+
+
 fn exec_response(req: Request, r: RequestResult) {
     let res_status: StatusCode;
     match r.typ {
@@ -86,6 +349,18 @@ fn exec_response(req: Request, r: RequestResult) {
         RequestResultType::RecordError => {
             res_status = StatusCode(404);
         },
+        RequestResultType::Gone => {
+            res_status = StatusCode(410);
+        },
+        RequestResultType::TooLarge => {
+            res_status = StatusCode(413);
+        },
+        RequestResultType::OverQuota => {
+            res_status = StatusCode(507);
+        },
+        RequestResultType::Conflict => {
+            res_status = StatusCode(409);
+        },
         _ => {
             res_status = StatusCode(500);
         },
@@ -94,15 +369,17 @@ fn exec_response(req: Request, r: RequestResult) {
         Some(v) => {
             let mut res = Response::from_string(v);
             res = res.with_status_code(res_status);
+            if let Some(cid) = r.m.as_ref().and_then(|m| to_cid(&m.digest)) {
+                res.add_header(Header::from_bytes(&b"X-Wala-Cid"[..], cid.as_bytes()).unwrap());
+            }
             req.respond(res);
             return;
         },
         None => {
             match r.f {
                 Some(v) => {
-                    let mut res = Response::from_file(v);
-                    res = res.with_status_code(res_status);
-                    req.respond(res);
+                    let ranged = r.typ == RequestResultType::Found && *req.method() == Method::Get;
+                    exec_file_response(req, v, res_status, r.m, ranged);
                     return;
                 },
                 None => {
@@ -116,9 +393,9 @@ fn exec_response(req: Request, r: RequestResult) {
 }
 
 
-fn exec_auth(auth_spec: AuthSpec, data: &File, data_length: usize) -> Option<AuthResult> {
+fn exec_auth<R: Read + Copy>(auth_spec: &AuthSpec, data: R, data_length: usize) -> Option<AuthResult> {
     #[cfg(feature = "dev")]
-    match mock_auth_check(&auth_spec, data, data_length) {
+    match mock_auth_check(auth_spec, data, data_length) {
         Ok(v) => {
             return Some(v);
         },
@@ -127,7 +404,25 @@ fn exec_auth(auth_spec: AuthSpec, data: &File, data_length: usize) -> Option<Aut
     }
 
     #[cfg(feature = "pgpauth")]
-    match pgp_auth_check(&auth_spec, data, data_length) {
+    match pgp_auth_check(auth_spec, data, data_length) {
+        Ok(v) => {
+            return Some(v);
+        },
+        Err(e) => {
+        },
+    }
+
+    #[cfg(feature = "ed25519auth")]
+    match ed25519_auth_check(auth_spec, data, data_length) {
+        Ok(v) => {
+            return Some(v);
+        },
+        Err(e) => {
+        },
+    }
+
+    #[cfg(feature = "ethauth")]
+    match eth_auth_check(auth_spec, data, data_length) {
         Ok(v) => {
             return Some(v);
         },
@@ -139,15 +434,50 @@ fn exec_auth(auth_spec: AuthSpec, data: &File, data_length: usize) -> Option<Aut
 }
 
 
-fn process_auth(auth_spec: AuthSpec, data: &File, data_length: usize) -> Option<AuthResult> {
+/// Check the signature of a request over the message binding it to method, reference and
+/// sequence.
+///
+/// A signature over the body alone is only accepted with `legacy` set, which leaves the sequence
+/// unsigned.
+fn process_auth(auth_spec: AuthSpec, method: &Method, url: &str, pointer_hash: HashAlgo, sequence: Option<u64>, data: &mut File, legacy: bool) -> Option<AuthResult> {
     if !auth_spec.valid() {
         let r = AuthResult{
             identity: vec!(),
             error: true,
+            signature: String::new(),
         };
         return Some(r);
     }
-    exec_auth(auth_spec, data, data_length)
+
+    let message = match signed_message(method.as_str(), url, pointer_hash, sequence, &*data) {
+        Ok(v) => {
+            v
+        },
+        Err(e) => {
+            error!("cannot read body to check signature: {}", e);
+            return None;
+        },
+    };
+    match exec_auth(&auth_spec, &message[..], message.len()) {
+        Some(v) => {
+            return Some(v);
+        },
+        None => {
+            if !legacy {
+                return None;
+            }
+        },
+    }
+
+    if let Err(e) = data.rewind() {
+        error!("cannot rewind body to check signature: {}", e);
+        return None;
+    }
+    let r = exec_auth(&auth_spec, &*data, 0);
+    if let Some(v) = &r {
+        warn!("accepted legacy signature over body only from {:?} for {} {}", v, method, url);
+    }
+    r
 }
 
 
@@ -177,7 +507,8 @@ fn auth_from_headers(headers: &[Header], method: &Method) -> Option<AuthSpec> {
 }
 
 
-fn process_request(req: &mut Request, f: &File) -> AuthResult {
+/// Authenticate a request, marking identities missing from the trust store, if any, as failed.
+fn process_request(req: &mut Request, url: &str, pointer_hash: HashAlgo, sequence: Option<u64>, f: &mut File, legacy_auth: bool, trust: Option<&TrustStore>) -> AuthResult {
     let headers = req.headers();
     let method = req.method();
 
@@ -185,7 +516,7 @@ fn process_request(req: &mut Request, f: &File) -> AuthResult {
     
     r = match auth_from_headers(headers, method) {
         Some(v) => {
-            process_auth(v, f, 0)
+            process_auth(v, method, url, pointer_hash, sequence, f, legacy_auth)
         },
         _ => {
             None
@@ -193,7 +524,13 @@ fn process_request(req: &mut Request, f: &File) -> AuthResult {
     };
 
     match r {
-        Some(v) => {
+        Some(mut v) => {
+            if let Some(trust) = trust {
+                if v.active() && !trust.allows(&v.identity) {
+                    warn!("rejecting untrusted identity {:?} for {} {}", v, method, url);
+                    v.error = true;
+                }
+            }
             return v;
         },
         _ => {},
@@ -203,27 +540,365 @@ fn process_request(req: &mut Request, f: &File) -> AuthResult {
     AuthResult{
          identity: vec!(),
          error: false,
+         signature: String::new(),
+    }
+}
+
+
+/// Process a single request, rejecting bodies over the upload limit with 413.
+///
+/// A declared `Content-Length` over the limit is rejected before the body is read, a chunked body
+/// is read no further than one byte past the limit. Whether the larger limit for authenticated
+/// uploads applies is decided from the presence of an `Authorization` header, as the signature
+/// cannot be checked until the body has been read. Once it has been, a body over the anonymous
+/// limit is still rejected if the signature does not authenticate the request.
+fn handle_request(mut req: Request, store: &dyn Store, quota: &QuotaPolicy, limits: &UploadLimits, aliases: &[HashAlgo], legacy_auth: bool, trust: Option<&TrustStore>) {
+    let url = String::from(&req.url()[1..]);
+    let method = req.method().clone();
+    let limit = limits.for_request(auth_from_headers(req.headers(), &method).is_some());
+    let mut expected_size = match req.body_length() {
+            Some(v) => {
+                v 
+            },
+            None => {
+                0
+            },
+        };
+    if let Some(max) = limit {
+        if expected_size as u64 > max {
+            debug!("declared body of {} bytes exceeds limit of {}", expected_size, max);
+            let _ = req.respond(Response::empty(StatusCode(413)));
+            return;
+        }
+    }
+    let meta = content_meta_from_headers(req.headers());
+    let hash = match hash_from_headers(req.headers()) {
+        Ok(v) => {
+            v
+        },
+        Err(e) => {
+            debug!("{}", e);
+            let _ = req.respond(Response::from_string(e).with_status_code(400));
+            return;
+        },
+    };
+    let pointer_hash = match pointer_hash_from_headers(req.headers()) {
+        Ok(v) => {
+            v
+        },
+        Err(e) => {
+            debug!("{}", e);
+            let _ = req.respond(Response::from_string(e).with_status_code(400));
+            return;
+        },
+    };
+    let sequence = match sequence_from_headers(req.headers()) {
+        Ok(v) => {
+            v
+        },
+        Err(e) => {
+            debug!("{}", e);
+            let _ = req.respond(Response::from_string(e).with_status_code(400));
+            return;
+        },
+    };
+    let f = req.as_reader();
+    let mut res: AuthResult = AuthResult{
+        identity: vec!(), 
+        error: false,
+        signature: String::new(),
+    };
+    let rw: Option<File> = match tempfile() {
+        Ok(mut v) => {
+            let copied = match limit {
+                Some(max) => {
+                    io_copy(&mut f.take(max + 1), &mut v)
+                },
+                None => {
+                    io_copy(f, &mut v)
+                },
+            };
+            if let (Some(max), Ok(n)) = (limit, &copied) {
+                if *n > max {
+                    debug!("body exceeds limit of {} bytes, aborting", max);
+                    let _ = req.respond(Response::empty(StatusCode(413)));
+                    return;
+                }
+            }
+            if expected_size == 0 {
+                if let Ok(n) = &copied {
+                    expected_size = *n as usize;
+                }
+            }
+            v.rewind();
+            res = process_request(&mut req, &url, pointer_hash.unwrap_or_default(), sequence, &mut v, legacy_auth, trust);
+            if !res.active() {
+                if let (Some(max), Ok(n)) = (limits.for_request(false), &copied) {
+                    if *n > max {
+                        debug!("unauthenticated body of {} bytes exceeds limit of {}, aborting", n, max);
+                        let _ = req.respond(Response::empty(StatusCode(413)));
+                        return;
+                    }
+                }
+            }
+            v.rewind();
+            Some(v)
+        },
+        Err(e) => {
+            None
+        },
+    };
+
+    let ctx = RequestContext {
+        sequence,
+        meta,
+        hash,
+        pointer_hash,
+        aliases,
+        quota: Some(quota),
+    };
+    let mut result: RequestResult;
+    match rw {
+        Some(v) => {
+            result = process_method(&method, url, v, expected_size, store, res, ctx);
+        },
+        None => {
+            let v = empty();
+            result = process_method(&method, url, v, expected_size, store, res, ctx);
+        },
+    };
+
+    exec_response(req, result);
+}
+
+
+fn run_gc(store: &FsStore, settings: &GcSettings) -> i32 {
+    let pins = match &settings.pins {
+        Some(v) => {
+            match read_pins(v) {
+                Ok(v) => {
+                    v
+                },
+                Err(e) => {
+                    error!("cannot read pins from {:?}: {}", v, e);
+                    return 1;
+                },
+            }
+        },
+        None => {
+            vec!()
+        },
+    };
+
+    let report = match gc_store(store, &pins, settings.grace, settings.keep_history, settings.dry_run) {
+        Ok(v) => {
+            v
+        },
+        Err(e) => {
+            error!("garbage collection failed: {}", e);
+            return 1;
+        },
+    };
+    for (digest, size) in &report.removed {
+        println!("{} {}", hex::encode(digest), size);
+    }
+    println!(
+        "{} {} records ({} bytes), kept {}, {} stale uploads",
+        if settings.dry_run { "would remove" } else { "removed" },
+        report.removed.len(),
+        report.removed_bytes(),
+        report.kept,
+        report.stale,
+        );
+    0
+}
+
+
+fn run_fsck(store: &FsStore, settings: &FsckSettings) -> i32 {
+    let report = match fsck_store(store, settings.quarantine) {
+        Ok(v) => {
+            v
+        },
+        Err(e) => {
+            error!("integrity check failed: {}", e);
+            return 1;
+        },
+    };
+    if settings.json {
+        println!("{}", report.to_json());
+    } else {
+        for v in &report.corrupt {
+            println!("corrupt {}", v);
+        }
+        for v in &report.dangling {
+            println!("dangling {}", v);
+        }
+        for v in &report.foreign {
+            println!("foreign {}", v);
+        }
+        for v in &report.unreadable {
+            println!("unreadable {}", v);
+        }
+        println!(
+            "{} records ok, {} corrupt ({} quarantined), {} dangling and {} foreign mutable references, {} unreadable",
+            report.ok,
+            report.corrupt.len(),
+            report.quarantined,
+            report.dangling.len(),
+            report.foreign.len(),
+            report.unreadable.len(),
+            );
+    }
+    match report.is_clean() {
+        true => {
+            0
+        },
+        false => {
+            2
+        },
+    }
+}
+
+
+fn run_migrate(path: &Path) -> i32 {
+    match migrate(path) {
+        Ok(v) => {
+            println!("moved {} files, relinked {} mutable references", v.moved, v.relinked);
+            0
+        },
+        Err(e) => {
+            error!("migration failed: {}", e);
+            1
+        },
+    }
+}
+
+
+/// Open the data directory for a maintenance command, exiting if that is not possible.
+fn open_data_dir(settings: &Settings, command: &str) -> FsStore {
+    if settings.s3.is_some() {
+        error!("{} only works on the data directory, not on S3 storage", command);
+        process::exit(1);
+    }
+    match FsStore::open(&settings.dir, settings.layout) {
+        Ok(v) => {
+            v
+        },
+        Err(e) => {
+            error!("cannot open store: {}", e);
+            process::exit(1);
+        },
+    }
+}
+
+fn open_store(settings: &Settings) -> io::Result<Box<dyn Store>> {
+    #[cfg(feature = "s3")]
+    if let Some(v) = &settings.s3 {
+        info!("storing records in bucket {} at {}", v.bucket, v.endpoint);
+        return Ok(Box::new(S3Store::new(v)));
+    }
+    Ok(Box::new(FsStore::open(&settings.dir, settings.layout)?))
+}
+
+
+/// Read the trust store, exiting if that is not possible.
+fn open_trust_store(path: &Path) -> TrustStore {
+    match TrustStore::open(path) {
+        Ok(v) => {
+            v
+        },
+        Err(e) => {
+            error!("cannot read trust store {:?}: {}", path, e);
+            process::exit(1);
+        },
     }
 }
 
 
 fn main() {
-    env_logger::init();
+    let settings = match Settings::from_args() {
+        Ok(v) => {
+            v
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        },
+    };
+
+    env_logger::Builder::new()
+        .filter_level(settings.loglevel)
+        .parse_default_env()
+        .init();
+
+    let base_path = settings.dir.as_path();
 
-    let base_path = Path::new(".");
+    match &settings.mode {
+        Mode::Gc(v) => {
+            let store = open_data_dir(&settings, "gc");
+            process::exit(run_gc(&store, v));
+        },
+        Mode::Fsck(v) => {
+            let store = open_data_dir(&settings, "fsck");
+            process::exit(run_fsck(&store, v));
+        },
+        Mode::Migrate => {
+            if settings.s3.is_some() {
+                error!("migrate only works on the data directory, not on S3 storage");
+                process::exit(1);
+            }
+            process::exit(run_migrate(base_path));
+        },
+        Mode::Serve => {},
+    }
 
-    let ip_addr = Ipv4Addr::from_str("0.0.0.0").unwrap();
-    let tcp_port: u16 = 8001;
-    let sock_addr = SocketAddrV4::new(ip_addr, tcp_port);
+    let handler_store = match open_store(&settings) {
+        Ok(v) => {
+            v
+        },
+        Err(e) => {
+            error!("cannot open store: {}", e);
+            process::exit(1);
+        },
+    };
+    let handler_trust = settings.trust_store.as_ref().map(|v| open_trust_store(v));
+
+    let ssl = match settings.ssl_config() {
+        Ok(v) => {
+            v
+        },
+        Err(e) => {
+            error!("{}", e);
+            process::exit(1);
+        },
+    };
     let srv_cfg = ServerConfig{
-        addr: sock_addr,
-        ssl: None,
+        addr: settings.addr(),
+        ssl,
+    };
+    let srv = match Server::new(srv_cfg) {
+        Ok(v) => {
+            info!("listening on {}", settings.addr());
+            v
+        },
+        Err(e) => {
+            error!("cannot listen on {}: {}", settings.addr(), e);
+            process::exit(1);
+        },
     };
-    let srv = Server::new(srv_cfg).unwrap();
+
+    let handler_quota = settings.quota.clone();
+    let handler_limits = settings.upload.clone();
+    let handler_aliases = settings.aliases.clone();
+    let handler_legacy_auth = settings.legacy_auth;
+    let pool = WorkerPool::new(settings.workers, settings.queue, Arc::new(move |req: Request| {
+        handle_request(req, handler_store.as_ref(), &handler_quota, &handler_limits, &handler_aliases, handler_legacy_auth, handler_trust.as_ref());
+    }));
+    info!("serving with {} workers, queue limit {}", settings.workers, settings.queue);
 
     loop {
         let b = srv.recv();
-        let mut req: Request;
+        let req: Request;
         match b {
             Ok(v) => req = v,
             Err(e) => {
@@ -232,47 +907,13 @@ fn main() {
             }
         };
 
-
-        let url = String::from(&req.url()[1..]);
-        let method = req.method().clone();
-        let expected_size = match req.body_length() {
-                Some(v) => {
-                    v 
-                },
-                None => {
-                    0
-                },
-            };
-        let f = req.as_reader();
-        let mut path = base_path.clone();
-        let mut res: AuthResult = AuthResult{
-            identity: vec!(), 
-            error: false,
-        };
-        let rw: Option<File> = match tempfile() {
-            Ok(mut v) => {
-                io_copy(f, &mut v);
-                v.rewind();
-                res = process_request(&mut req, &mut v);
-                v.rewind();
-                Some(v)
-            },
-            Err(e) => {
-                None
+        match pool.dispatch(req) {
+            Ok(()) => {},
+            Err(v) => {
+                error!("worker queue full, rejecting {} {}", v.method(), v.url());
+                let res = Response::empty(StatusCode(503));
+                let _ = v.respond(res);
             },
         };
-
-        let mut result: RequestResult;
-        match rw {
-            Some(v) => {
-                result = process_method(&method, url, v, expected_size, &path, res);
-            },
-            None => {
-                let v = empty();
-                result = process_method(&method, url, v, expected_size, &path, res);
-            },
-        };
-
-        exec_response(req, result);
     }
 }
@@ -0,0 +1,196 @@
+use std::fs::read_to_string;
+use std::io;
+use std::io::Write;
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use log::debug;
+use tempfile::NamedTempFile;
+
+use crate::record::persist;
+
+pub const META_SUFFIX: &str = ".meta";
+
+
+/// Descriptive properties of record content, as declared by the uploader.
+///
+/// They are kept in a sidecar file next to the immutable record, and never take part in the
+/// content digest.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct ContentMeta {
+    pub typ: Option<String>,
+    pub filename: Option<String>,
+}
+
+fn clean_value(s: &str) -> Option<String> {
+    let v = s.trim();
+    if v.is_empty() || v.chars().any(|c| c.is_control()) {
+        return None;
+    }
+    Some(v.to_string())
+}
+
+impl ContentMeta {
+    pub fn is_empty(&self) -> bool {
+        self.typ.is_none() && self.filename.is_none()
+    }
+
+    /// Set the content type from a `Content-Type` header value.
+    pub fn set_type(&mut self, s: &str) {
+        self.typ = clean_value(s);
+    }
+
+    /// Set the filename from the `filename` parameter of a `Content-Disposition` header value.
+    ///
+    /// Any directory components are discarded.
+    pub fn set_disposition(&mut self, s: &str) {
+        for param in s.split(';').skip(1) {
+            let (k, v) = match param.split_once('=') {
+                Some(v) => {
+                    v
+                },
+                None => {
+                    continue;
+                },
+            };
+            if !k.trim().eq_ignore_ascii_case("filename") {
+                continue;
+            }
+            let v = v.trim().trim_matches('"');
+            let name = match v.rsplit(['/', '\\']).next() {
+                Some(v) => {
+                    v
+                },
+                None => {
+                    continue;
+                },
+            };
+            if name == "." || name == ".." || name.contains('"') {
+                continue;
+            }
+            self.filename = clean_value(name);
+        }
+    }
+
+    /// Value for the `Content-Disposition` header of a response.
+    pub fn disposition(&self) -> Option<String> {
+        self.filename.as_ref().map(|v| format!("inline; filename=\"{}\"", v))
+    }
+
+    /// Serialize to the sidecar format, one `Key: value` per line.
+    pub fn to_sidecar(&self) -> String {
+        let mut s = String::new();
+        if let Some(v) = &self.typ {
+            s.push_str(&format!("Content-Type: {}\n", v));
+        }
+        if let Some(v) = &self.filename {
+            s.push_str(&format!("Filename: {}\n", v));
+        }
+        s
+    }
+
+    pub fn from_sidecar(s: &str) -> ContentMeta {
+        let mut meta = ContentMeta::default();
+        for l in s.lines() {
+            match l.split_once(": ") {
+                Some(("Content-Type", v)) => {
+                    meta.typ = clean_value(v);
+                },
+                Some(("Filename", v)) => {
+                    meta.filename = clean_value(v);
+                },
+                _ => {
+                    debug!("skipping unknown metadata line {:?}", l);
+                },
+            }
+        }
+        meta
+    }
+}
+
+/// Location of the metadata sidecar for the record at the given path.
+pub fn sidecar_path(record_path: &Path) -> PathBuf {
+    let mut s = record_path.as_os_str().to_os_string();
+    s.push(META_SUFFIX);
+    PathBuf::from(s)
+}
+
+/// Store content metadata for a record, replacing any previously stored metadata.
+///
+/// Empty metadata leaves existing metadata untouched.
+pub fn write_meta(record_path: &Path, meta: &ContentMeta) -> io::Result<()> {
+    if meta.is_empty() {
+        return Ok(());
+    }
+    let target = sidecar_path(record_path);
+    let dir = match target.parent() {
+        Some(v) => {
+            v
+        },
+        None => {
+            Path::new(".")
+        },
+    };
+    let mut tmp = NamedTempFile::new_in(dir)?;
+    tmp.write_all(meta.to_sidecar().as_bytes())?;
+    persist(tmp, &target)
+}
+
+/// Load content metadata for a record, if any has been stored.
+pub fn read_meta(record_path: &Path) -> ContentMeta {
+    match read_to_string(sidecar_path(record_path)) {
+        Ok(v) => {
+            ContentMeta::from_sidecar(&v)
+        },
+        Err(_) => {
+            ContentMeta::default()
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ContentMeta,
+        read_meta,
+        write_meta,
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_meta_disposition() {
+        let mut meta = ContentMeta::default();
+        meta.set_disposition("attachment; filename=\"foo.json\"");
+        assert_eq!(meta.filename, Some(String::from("foo.json")));
+
+        meta.set_disposition("inline; filename=../../etc/passwd");
+        assert_eq!(meta.filename, Some(String::from("passwd")));
+
+        let mut meta = ContentMeta::default();
+        meta.set_disposition("attachment");
+        assert_eq!(meta.filename, None);
+        meta.set_disposition("attachment; filename=\"..\"");
+        assert_eq!(meta.filename, None);
+    }
+
+    #[test]
+    fn test_meta_sidecar() {
+        let d = tempdir().unwrap();
+        let p = d.path().join("deadbeef");
+
+        assert!(read_meta(&p).is_empty());
+
+        let mut meta = ContentMeta::default();
+        meta.set_type("application/json");
+        meta.set_disposition("attachment; filename=foo.json");
+        write_meta(&p, &meta).unwrap();
+        assert!(d.path().join("deadbeef.meta").is_file());
+        assert_eq!(read_meta(&p), meta);
+
+        write_meta(&p, &ContentMeta::default()).unwrap();
+        assert_eq!(read_meta(&p), meta);
+    }
+}
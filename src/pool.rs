@@ -0,0 +1,137 @@
+use std::sync::{
+    Arc,
+    Mutex,
+};
+use std::sync::mpsc::{
+    sync_channel,
+    Receiver,
+    SyncSender,
+    TrySendError,
+};
+use std::thread;
+
+use log::{debug, error};
+
+
+/// Fixed set of worker threads fed from a bounded queue.
+///
+/// Jobs that do not fit in the queue are handed back to the caller, which is expected to reject
+/// them instead of blocking the accept loop.
+pub struct WorkerPool<T: Send + 'static> {
+    tx: Option<SyncSender<T>>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+fn work<T>(id: usize, rx: Arc<Mutex<Receiver<T>>>, handler: Arc<dyn Fn(T) + Send + Sync>) {
+    loop {
+        let job = match rx.lock() {
+            Ok(v) => {
+                v.recv()
+            },
+            Err(e) => {
+                error!("worker {} queue lock poisoned: {}", id, e);
+                return;
+            },
+        };
+        match job {
+            Ok(v) => {
+                handler(v);
+            },
+            Err(_) => {
+                debug!("worker {} shutting down", id);
+                return;
+            },
+        }
+    }
+}
+
+impl<T: Send + 'static> WorkerPool<T> {
+    pub fn new(workers: usize, queue: usize, handler: Arc<dyn Fn(T) + Send + Sync>) -> WorkerPool<T> {
+        let (tx, rx) = sync_channel(queue);
+        let rx = Arc::new(Mutex::new(rx));
+        let mut handles = vec!();
+        for i in 0..workers {
+            let rx = rx.clone();
+            let handler = handler.clone();
+            handles.push(thread::spawn(move || work(i, rx, handler)));
+        }
+        WorkerPool {
+            tx: Some(tx),
+            workers: handles,
+        }
+    }
+
+    /// Queue a job, or return it if the queue is full.
+    pub fn dispatch(&self, job: T) -> Result<(), T> {
+        match self.tx.as_ref().unwrap().try_send(job) {
+            Ok(()) => {
+                Ok(())
+            },
+            Err(TrySendError::Full(v)) => {
+                Err(v)
+            },
+            Err(TrySendError::Disconnected(v)) => {
+                error!("all workers have exited");
+                Err(v)
+            },
+        }
+    }
+}
+
+impl<T: Send + 'static> Drop for WorkerPool<T> {
+    fn drop(&mut self) {
+        // closing the channel makes every worker return once the queue is drained
+        self.tx.take();
+        for h in self.workers.drain(..) {
+            let _ = h.join();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::WorkerPool;
+    use std::sync::{
+        Arc,
+        Barrier,
+        Mutex,
+    };
+
+    #[test]
+    fn test_pool_dispatch() {
+        let seen = Arc::new(Mutex::new(vec!()));
+        let seen_worker = seen.clone();
+        let pool = WorkerPool::new(2, 4, Arc::new(move |v: u32| {
+            seen_worker.lock().unwrap().push(v);
+        }));
+        for i in 0..4 {
+            while pool.dispatch(i).is_err() {}
+        }
+        drop(pool);
+
+        let mut r = seen.lock().unwrap().clone();
+        r.sort();
+        assert_eq!(r, vec!(0, 1, 2, 3));
+    }
+
+    #[test]
+    fn test_pool_saturated() {
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_worker = barrier.clone();
+        let pool = WorkerPool::new(1, 1, Arc::new(move |_: u32| {
+            barrier_worker.wait();
+        }));
+
+        // first job occupies the worker, second fills the queue
+        pool.dispatch(1).unwrap();
+        let mut queued = false;
+        while !queued {
+            queued = pool.dispatch(2).is_ok();
+        }
+        assert_eq!(pool.dispatch(3), Err(3));
+
+        barrier.wait();
+        barrier.wait();
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+use std::fs::{
+    create_dir_all,
+    read_to_string,
+};
+use std::io;
+use std::io::Write;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::str::FromStr;
+use std::sync::{
+    Mutex,
+    MutexGuard,
+};
+
+use log::debug;
+use tempfile::NamedTempFile;
+
+use crate::record::persist;
+
+pub const QUOTA_DIR: &str = ".quota";
+
+/// Serializes quota checks with the updates they admit.
+static QUOTA_LOCK: Mutex<()> = Mutex::new(());
+
+
+/// Storage limits for a single identity, `None` meaning unlimited.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Limits {
+    pub bytes: Option<u64>,
+    pub objects: Option<u64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum QuotaError {
+    /// The upload alone is larger than the byte limit.
+    TooLarge,
+    /// The upload would take the identity past one of its limits.
+    Exceeded,
+}
+
+/// Default limits for authenticated uploads, with per-identity overrides.
+#[derive(Debug, Default, Clone)]
+pub struct QuotaPolicy {
+    pub default: Limits,
+    pub overrides: HashMap<Vec<u8>, Limits>,
+}
+
+fn parse_limit(s: &str) -> Result<Option<u64>, String> {
+    if s == "-" {
+        return Ok(None);
+    }
+    match u64::from_str(s) {
+        Ok(v) => {
+            Ok(Some(v))
+        },
+        Err(e) => {
+            Err(format!("invalid limit {:?}: {}", s, e))
+        },
+    }
+}
+
+impl QuotaPolicy {
+    /// Parse per-identity overrides, one `<identity hex> <bytes> <objects>` per line.
+    ///
+    /// A limit of `-` means unlimited. Empty lines and lines starting with `#` are skipped.
+    pub fn parse_overrides(&mut self, s: &str) -> Result<(), String> {
+        for l in s.lines() {
+            let l = l.trim();
+            if l.is_empty() || l.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = l.split_whitespace().collect();
+            if fields.len() != 3 {
+                return Err(format!("invalid quota line {:?}", l));
+            }
+            let identity = match hex::decode(fields[0]) {
+                Ok(v) => {
+                    v
+                },
+                Err(e) => {
+                    return Err(format!("invalid identity {:?}: {}", fields[0], e));
+                },
+            };
+            let limits = Limits {
+                bytes: parse_limit(fields[1])?,
+                objects: parse_limit(fields[2])?,
+            };
+            self.overrides.insert(identity, limits);
+        }
+        Ok(())
+    }
+
+    pub fn limits_for(&self, identity: &[u8]) -> &Limits {
+        match self.overrides.get(identity) {
+            Some(v) => {
+                v
+            },
+            None => {
+                &self.default
+            },
+        }
+    }
+
+    /// Check whether storing `size` bytes under `pointer` keeps the identity within its limits.
+    ///
+    /// Replacing the target of an existing mutable reference releases the size of the old one.
+    pub fn check(&self, identity: &[u8], usage: &Usage, pointer: &[u8], size: u64) -> Result<(), QuotaError> {
+        let limits = self.limits_for(identity);
+        let previous = usage.entries.get(pointer);
+        if let Some(max) = limits.bytes {
+            if size > max {
+                return Err(QuotaError::TooLarge);
+            }
+            let bytes = usage.bytes() - previous.copied().unwrap_or(0) + size;
+            if bytes > max {
+                return Err(QuotaError::Exceeded);
+            }
+        }
+        if let Some(max) = limits.objects {
+            let objects = usage.objects() + if previous.is_some() { 0 } else { 1 };
+            if objects > max {
+                return Err(QuotaError::Exceeded);
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// Storage held by an identity, as the size of the current target of each of its mutable
+/// references.
+#[derive(Debug, Default, Clone)]
+pub struct Usage {
+    entries: HashMap<Vec<u8>, u64>,
+}
+
+impl Usage {
+    pub fn bytes(&self) -> u64 {
+        self.entries.values().sum()
+    }
+
+    pub fn objects(&self) -> u64 {
+        self.entries.len() as u64
+    }
+
+    pub fn set(&mut self, pointer: Vec<u8>, size: u64) {
+        self.entries.insert(pointer, size);
+    }
+
+    pub fn remove(&mut self, pointer: &[u8]) {
+        self.entries.remove(pointer);
+    }
+
+    /// Parse stored usage, one `<pointer hex> <size>` per line.
+    pub fn parse(s: &str) -> io::Result<Usage> {
+        let mut usage = Usage::default();
+        for l in s.lines() {
+            let entry = l.split_once(' ').and_then(|(k, v)| {
+                Some((hex::decode(k).ok()?, u64::from_str(v).ok()?))
+            });
+            match entry {
+                Some((k, v)) => {
+                    usage.set(k, v);
+                },
+                None => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("corrupt usage entry {:?}", l)));
+                },
+            }
+        }
+        Ok(usage)
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut s = String::new();
+        for (k, v) in &self.entries {
+            s.push_str(&format!("{} {}\n", hex::encode(k), v));
+        }
+        s
+    }
+}
+
+fn usage_path(path: &Path, identity: &[u8]) -> PathBuf {
+    path.join(QUOTA_DIR).join(hex::encode(identity))
+}
+
+/// Take the quota lock, to be held from the check until the usage has been saved.
+pub fn lock() -> MutexGuard<'static, ()> {
+    QUOTA_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Load the usage of an identity from the store.
+pub fn load_usage(path: &Path, identity: &[u8]) -> io::Result<Usage> {
+    let s = match read_to_string(usage_path(path, identity)) {
+        Ok(v) => {
+            v
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(Usage::default());
+        },
+        Err(e) => {
+            return Err(e);
+        },
+    };
+    Usage::parse(&s)
+}
+
+/// Save the usage of an identity to the store.
+pub fn save_usage(path: &Path, identity: &[u8], usage: &Usage) -> io::Result<()> {
+    let dir = path.join(QUOTA_DIR);
+    create_dir_all(&dir)?;
+    let mut tmp = NamedTempFile::new_in(&dir)?;
+    tmp.write_all(usage.serialize().as_bytes())?;
+    debug!("identity {} uses {} bytes in {} objects", hex::encode(identity), usage.bytes(), usage.objects());
+    persist(tmp, &usage_path(path, identity))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        load_usage,
+        save_usage,
+        Limits,
+        QuotaError,
+        QuotaPolicy,
+        Usage,
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_quota_check() {
+        let mut policy = QuotaPolicy {
+            default: Limits {
+                bytes: Some(10),
+                objects: Some(2),
+            },
+            ..Default::default()
+        };
+        policy.parse_overrides("# generous\nf00f - -\n").unwrap();
+
+        let mut usage = Usage::default();
+        assert_eq!(policy.check(b"foo", &usage, b"a", 11), Err(QuotaError::TooLarge));
+        assert_eq!(policy.check(b"foo", &usage, b"a", 6), Ok(()));
+        usage.set(b"a".to_vec(), 6);
+        assert_eq!(policy.check(b"foo", &usage, b"b", 6), Err(QuotaError::Exceeded));
+
+        // replacing a target releases the old size
+        assert_eq!(policy.check(b"foo", &usage, b"a", 10), Ok(()));
+
+        usage.set(b"b".to_vec(), 1);
+        assert_eq!(policy.check(b"foo", &usage, b"c", 1), Err(QuotaError::Exceeded));
+        assert_eq!(policy.check(&[0xf0, 0x0f], &usage, b"c", 100), Ok(()));
+    }
+
+    #[test]
+    fn test_quota_overrides_invalid() {
+        let mut policy = QuotaPolicy::default();
+        assert!(policy.parse_overrides("f00f 10\n").is_err());
+        assert!(policy.parse_overrides("xyz 10 10\n").is_err());
+        assert!(policy.parse_overrides("f00f ten 10\n").is_err());
+    }
+
+    #[test]
+    fn test_quota_usage_persist() {
+        let d = tempdir().unwrap();
+        let usage = load_usage(d.path(), b"foo").unwrap();
+        assert_eq!(usage.objects(), 0);
+
+        let mut usage = Usage::default();
+        usage.set(b"a".to_vec(), 6);
+        usage.set(b"b".to_vec(), 4);
+        save_usage(d.path(), b"foo", &usage).unwrap();
+
+        let mut usage = load_usage(d.path(), b"foo").unwrap();
+        assert_eq!(usage.bytes(), 10);
+        assert_eq!(usage.objects(), 2);
+
+        usage.remove(b"a");
+        save_usage(d.path(), b"foo", &usage).unwrap();
+        let usage = load_usage(d.path(), b"foo").unwrap();
+        assert_eq!(usage.bytes(), 4);
+    }
+}
@@ -2,22 +2,21 @@ use std::str::FromStr;
 use std::io;
 use std::convert::Infallible;
 use std::fs::File;
-use std::io::{
-    Write,
-    Read,
-};
-use std::os::unix::fs::symlink;
-use std::path::{
-    PathBuf,
-    Path,
-};
-use std::fs::copy as fs_copy;
+use std::io::Read;
+use std::path::Path;
 use std::error::Error;
-use sha2::{Sha256, Digest};
 use std::fmt;
 
 use crate::auth::AuthResult;
-use tiny_http::Request;
+use crate::hash::{
+    HashAlgo,
+    HashingReader,
+};
+use crate::meta::ContentMeta;
+use crate::store::{
+    RecordReader,
+    Store,
+};
 use tempfile::NamedTempFile;
 
 use log::{debug, info, error};
@@ -31,12 +30,29 @@ pub enum RequestResultType {
     AuthError,
     InputError,
     RecordError,
+    Gone,
+    TooLarge,
+    OverQuota,
+    Conflict,
 }
 
 pub struct RequestResult {
     pub typ: RequestResultType,
     pub v: Option<String>,
-    pub f: Option<File>,
+    pub f: Option<Box<dyn RecordReader>>,
+    /// Boxed, as results are passed around as errors, and those are best kept small.
+    pub m: Option<Box<RecordMeta>>,
+}
+
+/// Properties of a resolved record that are passed on to the client alongside its content.
+pub struct RecordMeta {
+    /// Digest of the immutable record the request resolved to.
+    pub digest: Vec<u8>,
+    /// Content properties declared when the record was stored.
+    pub content: ContentMeta,
+    /// Current sequence of the mutable reference the request resolved through, 0 if it did not
+    /// resolve through one or the reference has no sequence yet.
+    pub sequence: u64,
 }
 
 impl fmt::Display for RequestResult {
@@ -67,23 +83,20 @@ impl Error for RequestResult {
 
 pub struct Record {
     pub digest: Vec<u8>,
-    pub path: PathBuf,
+    /// Size of the content stored, none for deletions.
+    pub size: u64,
 }
 
 pub struct ResourceKey { 
     v: Vec<u8>,
+    algo: HashAlgo,
 }
 
 impl FromStr for ResourceKey {
     type Err = Infallible;
 
     fn from_str(s: &str) -> Result<ResourceKey, Infallible> {
-            let mut h = Sha256::new();
-            h.update(&s[..]);
-            let k = ResourceKey{
-                v: h.finalize().to_vec(),
-            };
-            Ok(k)
+            Ok(ResourceKey::with_algo(s, HashAlgo::Sha256))
     }
 }
 
@@ -94,146 +107,240 @@ impl fmt::Display for ResourceKey {
 }
 
 impl ResourceKey {
+    /// Key for deriving mutable references with the given algorithm.
+    ///
+    /// The reference for a key is `H(H(key) || identity)`. With Keccak-256 this can be computed
+    /// on-chain as `keccak256(abi.encodePacked(keccak256(bytes(key)), identity))`.
+    ///
+    /// Clients select the algorithm with the `X-Wala-Pointer-Hash` header, SHA-256 by default,
+    /// independently of the `X-Wala-Hash` algorithm the content is stored with. Updates and
+    /// deletions of a reference must send the same one.
+    pub fn with_algo(s: &str, algo: HashAlgo) -> ResourceKey {
+        ResourceKey{
+            v: algo.digest(s.as_bytes()),
+            algo,
+        }
+    }
+
     pub fn pointer_for(&self, subject: &AuthResult) -> Vec<u8> {
-        let mut h = Sha256::new();
+        let mut h = self.algo.hasher();
         debug!("update {:?} {:?}", hex::encode(&self.v), hex::encode(&subject.identity));
         h.update(&self.v);
         h.update(&subject.identity);
-        h.finalize().to_vec()
+        h.finalize()
     }
 }
 
 
-pub fn put_immutable(path: &Path, mut f: impl Read, expected_size: usize) -> Result<Record, RequestResult> {
-    let z: Vec<u8>;
-    let hash: String;
-    let mut total_size: usize = 0;
-    let tempfile = match NamedTempFile::new() {
-        Ok(of) => {
-            debug!("writing to tempfile {:?} expected size {}", of.path(), expected_size);
-            let mut buf: [u8; 65535] = [0; 65535];
-            let mut h = Sha256::new();
-            loop {
-                match f.read(&mut buf[..]) {
-                    Ok(v) => {
-                        if v == 0 {
-                            break;
-                        }
-                        total_size += v;
-                        let data = &buf[..v];
-                        h.update(data);
-                        of.as_file().write(data);
-                    },
-                    Err(e) => {
-                        error!("cannot read from request body: {}", e);
-                        let err = RequestResult{
-                            typ: RequestResultType::ReadError,
-                            v: None,
-                            f: None,
-                        };
-                        return Err(err);
-                    },
-                }
-            }
-    
-            if expected_size > 0 {
-                if expected_size != total_size {
-                    let err = RequestResult{
-                        typ: RequestResultType::ReadError,
-                        v: None,
-                        f: None,
-                    };
-                    return Err(err);
-                }
-            }
+pub fn write_error(e: io::Error, ctx: &str) -> RequestResult {
+    error!("{}: {}", ctx, e);
+    RequestResult{
+        typ: RequestResultType::WriteError,
+        v: None,
+        f: None,
+        m: None,
+    }
+}
 
-            z = h.finalize().to_vec();
-            hash = hex::encode(&z);
-            info!("have hash {} for content", hash);
-            of
-        },
-        Err(e) => {
-            let err = RequestResult{
-                typ: RequestResultType::WriteError,
-                v: None,
-                f: None,
-            };
-            return Err(err);
+/// Sync the directory holding `target`, so that a rename into it survives a crash.
+fn sync_parent(target: &Path) -> io::Result<()> {
+    if let Some(dir) = target.parent() {
+        let dir = match dir.as_os_str().is_empty() {
+            true => {
+                Path::new(".")
+            },
+            false => {
+                dir
+            },
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Flush a staged file to disk and atomically move it into place.
+///
+/// The temporary file must be on the same filesystem as the target, and the directory entry is
+/// synced too so the rename itself survives a crash.
+pub fn persist(tmp: NamedTempFile, target: &Path) -> io::Result<()> {
+    tmp.as_file().sync_all()?;
+    tmp.persist(target).map_err(|e| e.error)?;
+    sync_parent(target)
+}
+
+/// Like [`persist`], but fails with [`io::ErrorKind::AlreadyExists`] instead of replacing
+/// anything already at the target.
+pub fn persist_new(tmp: NamedTempFile, target: &Path) -> io::Result<()> {
+    tmp.as_file().sync_all()?;
+    tmp.persist_noclobber(target).map_err(|e| e.error)?;
+    sync_parent(target)
+}
+
+/// Store content as an immutable record, also reachable by its digest in each of the `aliases`
+/// algorithms.
+pub fn put_immutable(store: &dyn Store, f: impl Read, expected_size: usize, algo: HashAlgo, aliases: &[HashAlgo], meta: &ContentMeta) -> Result<Record, RequestResult> {
+    let mut f = HashingReader::new(f, aliases);
+    let digest = store.put_blob(&mut f, expected_size, algo, meta)?;
+    let size = f.size();
+    for (alias_algo, v) in f.finalize() {
+        if alias_algo == algo {
+            continue;
         }
-    };
+        let alias = alias_algo.name(&v);
+        store.set_alias(&alias, &digest)?;
+        debug!("record {} also reachable as {}", hex::encode(&digest), hex::encode(&alias));
+    }
+    Ok(Record{
+        digest,
+        size,
+    })
+}
 
-    let final_path_buf = path.join(&hash);
-    let final_path = final_path_buf.as_path();
-    fs_copy(tempfile.path(), final_path);
+/// Point a mutable reference at a stored record.
+///
+/// The sequence must be greater than that of any earlier update of the reference, so that old
+/// signed updates cannot be replayed to roll it back.
+pub fn set_mutable(store: &dyn Store, pointer: Vec<u8>, record: &Record, signature: &str, sequence: u64) -> Result<Record, RequestResult> {
+    store.set_pointer(&pointer, &record.digest, signature, sequence)?;
+    debug!("mutable reference {} now points to {}", hex::encode(&pointer), hex::encode(&record.digest));
+    Ok(Record{
+        digest: pointer,
+        size: record.size,
+    })
+}
 
-    let r = Record{
-        digest: z,
-        path: final_path_buf,
-    };
-    Ok(r)
+/// Store content and point a mutable reference at it, see [`set_mutable`].
+#[cfg(test)]
+#[allow(clippy::too_many_arguments)]
+pub fn put_mutable(store: &dyn Store, pointer: Vec<u8>, f: impl Read, expected_size: usize, algo: HashAlgo, aliases: &[HashAlgo], meta: &ContentMeta, signature: &str, sequence: u64) -> Result<Record, RequestResult> {
+    let record = put_immutable(store, f, expected_size, algo, aliases, meta)?;
+    set_mutable(store, pointer, &record, signature, sequence)
+}
+
+/// Remove a mutable reference, leaving a tombstone in its revision log.
+///
+/// The record it pointed to stays addressable by its digest.
+pub fn delete_mutable(store: &dyn Store, pointer: Vec<u8>, signature: &str, sequence: u64) -> Result<Record, RequestResult> {
+    store.delete_pointer(&pointer, signature, sequence)?;
+    info!("mutable reference {} deleted", hex::encode(&pointer));
+    Ok(Record{
+        digest: pointer,
+        size: 0,
+    })
 }
 
-pub fn put_mutable(pointer: Vec<u8>, path: &Path, mut f: impl Read, expected_size: usize) -> Result<Record, RequestResult> {
-    let mutable_ref = hex::encode(&pointer);
-    let link_path_buf = path.join(&mutable_ref);
-    
-    let record = put_immutable(path, f, expected_size);
-    match record {
+/// Check whether a mutable reference has been deleted and not published again since.
+pub fn is_deleted(store: &dyn Store, pointer: &[u8]) -> bool {
+    if store.resolve(pointer).is_some() {
+        return false;
+    }
+    match store.history(pointer) {
         Ok(v) => {
-            symlink(&v.path, &link_path_buf);
-            let r = Record{
-                digest: pointer,
-                path: link_path_buf.clone(),
-            };
-            return Ok(r);
+            match v.last() {
+                Some(revision) => {
+                    revision.is_tombstone()
+                },
+                None => {
+                    false
+                },
+            }
+        },
+        Err(_) => {
+            false
         },
-        Err(e) => {
-            return Err(e);
-        }
     }
 }
 
-pub fn get(pointer: Vec<u8>, path: &Path) -> Option<File> { //{ impl Read> {
-    let path_canon = match path.canonicalize() {
+/// Open a past revision of a mutable reference by its index in the revision log.
+///
+/// Fails with [`RequestResultType::Gone`] if the revision is in the log but its content has been
+/// garbage collected, and with [`RequestResultType::RecordError`] if there is no such revision.
+pub fn get_revision(store: &dyn Store, pointer: &[u8], idx: usize) -> Result<(Box<dyn RecordReader>, RecordMeta), RequestResult> {
+    let missing = || RequestResult{
+        typ: RequestResultType::RecordError,
+        v: Some(String::new()),
+        f: None,
+        m: None,
+    };
+    let revisions = match store.history(pointer) {
         Ok(v) => {
             v
         },
-        Err(e) => {
-            return None;
+        Err(_) => {
+            return Err(missing());
+        },
+    };
+    let revision = match revisions.get(idx) {
+        Some(v) if !v.is_tombstone() && !v.corrupt => {
+            v
+        },
+        _ => {
+            return Err(missing());
         },
     };
-    match File::open(path_canon) {
-        Ok(f) => {
-            return Some(f);
+    match get(store, &revision.digest) {
+        Some(v) => {
+            Ok(v)
+        },
+        None => {
+            debug!("revision {} of {} has been collected", idx, hex::encode(pointer));
+            Err(RequestResult{
+                typ: RequestResultType::Gone,
+                v: Some(String::new()),
+                f: None,
+                m: None,
+            })
+        },
+    }
+}
+
+/// Current sequence of a mutable reference, the highest in its revision log.
+pub fn current_sequence(store: &dyn Store, pointer: &[u8]) -> u64 {
+    match store.history(pointer) {
+        Ok(v) => {
+            v.iter().map(|v| v.sequence).max().unwrap_or(0)
         },
-        _ => {},
+        Err(_) => {
+            0
+        },
+    }
+}
+
+/// Open the immutable record a digest or mutable reference resolves to.
+pub fn get(store: &dyn Store, key: &[u8]) -> Option<(Box<dyn RecordReader>, RecordMeta)> {
+    let mut m = store.resolve(key)?;
+    let f = store.open_reader(&m.digest)?;
+    if m.digest != key {
+        m.sequence = current_sequence(store, key);
     }
-    None
+    Some((f, m))
 }
 
 #[cfg(test)]
 mod tests {
     use super::ResourceKey;
     use super::AuthResult;
+    use super::ContentMeta;
     use super::{
-        put_immutable,
+        delete_mutable,
+        get,
+        is_deleted,
         put_mutable,
     };
-    use std::fs::read;
-    use tempfile::tempdir;
+    use crate::hash::HashAlgo;
+    use crate::store::mem::MemStore;
     use hex;
 
-    use env_logger;
-    use log::{debug, info, error};
-
     #[test]
     fn test_pointer() {
         let resource = ResourceKey{
             v: vec!(0x66, 0x6f, 0x6f),
+            algo: HashAlgo::Sha256,
         };
         let subject = AuthResult{
             identity: vec!(0x62, 0x61, 0x72),
             error: false,
+            signature: String::new(),
         };
         let r = resource.pointer_for(&subject);
 
@@ -241,46 +348,36 @@ mod tests {
         assert_eq!(r, foobar_digest);
     }
 
+    /// Vectors for `keccak256(abi.encodePacked(keccak256(bytes(key)), identity))`, shared with the
+    /// contracts deriving references on-chain.
     #[test]
-    fn test_immutable() {
-        let d = tempdir().unwrap();
-        let b = b"foo";
-        put_immutable(d.path().clone(), &b[..], 3);
-        
-        let immutable_path_buf = d.path().join("2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae");
-        let immutable_path = immutable_path_buf.as_path();
-        debug!(">>>>> checking immutable path {:?}", immutable_path);
-        assert!(immutable_path.is_file());
-
-        let mut r = read(immutable_path).unwrap();
-        assert_eq!(r, b.to_vec());
-
+    fn test_pointer_keccak() {
+        for (key, identity, pointer) in [
+            ("foo", "00000000000000000000000000000000deadbeef", "cc00885d367985a110292614f660920eafddb1029b80140d58584e9e1f6c0d67"),
+            ("deadbeef", "666f6f", "a1f14e57ee7dd1b48356107cd526617e46817024237d57e4fd018061b83c51e1"),
+            ] {
+            let resource = ResourceKey::with_algo(key, HashAlgo::Keccak256);
+            let subject = AuthResult{
+                identity: hex::decode(identity).unwrap(),
+                error: false,
+                signature: String::new(),
+            };
+            assert_eq!(hex::encode(resource.pointer_for(&subject)), pointer);
+        }
     }
-    
-    #[test]
-    fn test_mutable() {
-        env_logger::init();
 
-        let d = tempdir().unwrap();
-        let b = b"foo";
+    #[test]
+    fn test_mutable_mem() {
+        let store = MemStore::default();
         let ptr = b"foobar";
-        put_mutable(ptr.to_vec(), d.path().clone(), &b[..], 3);
-
-        let foobar_hex = hex::encode(ptr);
-        let mutable_path_buf = d.path().join(foobar_hex);
-        let mutable_path = mutable_path_buf.as_path();
-        debug!(">>>>> checking mutable path {:?}", mutable_path);
-        assert!(mutable_path.is_symlink());
-
-        let mut r = read(mutable_path).unwrap();
-        assert_eq!(r, b.to_vec());
-    
-        let immutable_path_buf = d.path().join("2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae");
-        let immutable_path = immutable_path_buf.as_path();
-        debug!(">>>>> checking immutable path {:?}", immutable_path);
-        assert!(immutable_path.is_file());
-
-        let mut r = read(immutable_path).unwrap();
-        assert_eq!(r, b.to_vec());
+        put_mutable(&store, ptr.to_vec(), &b"foo"[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default(), "", 1).unwrap();
+
+        let (f, m) = get(&store, ptr).unwrap();
+        assert_eq!(std::io::read_to_string(f).unwrap(), "foo");
+        assert_eq!(hex::encode(m.digest), "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae");
+
+        delete_mutable(&store, ptr.to_vec(), "", 2).unwrap();
+        assert!(get(&store, ptr).is_none());
+        assert!(is_deleted(&store, ptr));
     }
 }
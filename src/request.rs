@@ -1,4 +1,3 @@
-use std::path::Path;
 use std::str::FromStr;
 use tiny_http::{
     Method,
@@ -8,15 +7,33 @@ use tiny_http::{
 };
 use crate::record::{
     put_immutable,
-    put_mutable,
+    set_mutable,
     get as get_record,
+    get_revision,
+    delete_mutable,
+    is_deleted,
     ResourceKey,
+    RecordMeta,
     RequestResult,
     RequestResultType,
 };
 use crate::auth::{
     AuthResult,
 };
+use crate::cid::from_cid;
+use crate::hash::{
+    parse_name,
+    HashAlgo,
+};
+use crate::history::render as history_render;
+use crate::meta::ContentMeta;
+use crate::quota::{
+    lock as quota_lock,
+    QuotaError,
+    QuotaPolicy,
+    Usage,
+};
+use crate::store::Store;
 use std::io::Read;
 
 use log::{
@@ -24,7 +41,228 @@ use log::{
     error,
 };
 
-pub fn process_method(method: &Method, url: String, mut f: impl Read, expected_size: usize, path: &Path, auth_result: AuthResult) -> RequestResult {
+/// Serve the revision log of a mutable reference.
+///
+/// `<pointer>/history` lists all revisions, `<pointer>/history/<index>` serves the content of a
+/// single revision.
+fn process_history(pointer_hex: &str, rest: &str, store: &dyn Store) -> RequestResult {
+    let pointer = match hex::decode(pointer_hex) {
+        Ok(v) => {
+            v
+        },
+        Err(e) => {
+            return RequestResult {
+                typ: RequestResultType::InputError,
+                v: Some(format!("{}", e)),
+                f: None,
+                m: None,
+            };
+        },
+    };
+
+    let idx = match rest.split_once('/') {
+        None if rest == "history" => {
+            None
+        },
+        Some(("history", v)) => {
+            match usize::from_str(v) {
+                Ok(v) => {
+                    Some(v)
+                },
+                Err(e) => {
+                    return RequestResult {
+                        typ: RequestResultType::InputError,
+                        v: Some(format!("{}", e)),
+                        f: None,
+                        m: None,
+                    };
+                },
+            }
+        },
+        _ => {
+            return RequestResult {
+                typ: RequestResultType::InputError,
+                v: Some(String::new()),
+                f: None,
+                m: None,
+            };
+        },
+    };
+
+    match idx {
+        Some(v) => {
+            match get_revision(store, &pointer, v) {
+                Ok((f, m)) => {
+                    RequestResult {
+                        typ: RequestResultType::Found,
+                        v: None,
+                        f: Some(f),
+                        m: Some(Box::new(m)),
+                    }
+                },
+                Err(e) => {
+                    e
+                },
+            }
+        },
+        None => {
+            match store.history(&pointer) {
+                Ok(v) => {
+                    RequestResult {
+                        typ: RequestResultType::Found,
+                        v: Some(history_render(&v)),
+                        f: None,
+                        m: None,
+                    }
+                },
+                Err(e) => {
+                    debug!("no history for {}: {}", pointer_hex, e);
+                    RequestResult {
+                        typ: RequestResultType::RecordError,
+                        v: Some(String::new()),
+                        f: None,
+                        m: None,
+                    }
+                },
+            }
+        },
+    }
+}
+
+/// Hash algorithm for an immutable upload, from the `X-Wala-Hash` header or an `/<algo>` path.
+fn immutable_algo(url: &str, hash: Option<HashAlgo>) -> Result<HashAlgo, String> {
+    let from_url = HashAlgo::from_str(url.trim_end_matches('/')).ok();
+    match (from_url, hash) {
+        (Some(a), Some(b)) if a != b => {
+            Err(format!("hash algorithm {} in path conflicts with {} in header", a, b))
+        },
+        (a, b) => {
+            Ok(a.or(b).unwrap_or_default())
+        },
+    }
+}
+
+/// Name of the record a GET path refers to.
+///
+/// Records can be addressed by their name, as `<algo>/<digest>` by the algorithm and bare digest,
+/// or by a base32 CIDv1. Multihash SHA-256 names are mapped to the bare digest SHA-256 records are
+/// stored under.
+fn record_name(url: &str) -> Result<Vec<u8>, String> {
+    let (algo, digest_hex) = match url.split_once('/') {
+        Some((a, b)) => {
+            (Some(HashAlgo::from_str(a)?), b)
+        },
+        None => {
+            (None, url)
+        },
+    };
+    let v = match hex::decode(digest_hex) {
+        Ok(v) => {
+            v
+        },
+        Err(e) => {
+            if algo.is_none() {
+                if let Some(v) = from_cid(url) {
+                    return Ok(v);
+                }
+            }
+            return Err(format!("{}", e));
+        },
+    };
+    match algo {
+        Some(algo) if v.len() != algo.digest_len() => {
+            Err(format!("invalid {} digest length {}", algo, v.len()))
+        },
+        Some(algo) => {
+            Ok(algo.name(&v))
+        },
+        None => {
+            match parse_name(&v) {
+                Some((algo, digest)) => {
+                    Ok(algo.name(digest))
+                },
+                None => {
+                    Ok(v)
+                },
+            }
+        },
+    }
+}
+
+/// Sequence an update of a mutable reference carries, which it cannot do without.
+fn require_sequence(sequence: Option<u64>) -> Result<u64, RequestResult> {
+    match sequence {
+        Some(v) => {
+            Ok(v)
+        },
+        None => {
+            Err(RequestResult{
+                typ: RequestResultType::InputError,
+                v: Some(String::from("updates of mutable references need a sequence")),
+                f: None,
+                m: None,
+            })
+        },
+    }
+}
+
+/// Usage of an identity, if storing `size` bytes at the reference keeps it within its quota.
+fn check_quota(store: &dyn Store, quota: &QuotaPolicy, identity: &[u8], pointer: &[u8], size: u64) -> Result<Usage, RequestResult> {
+    let usage = match store.load_usage(identity) {
+        Ok(v) => {
+            v
+        },
+        Err(e) => {
+            error!("cannot load quota usage: {:?}", e);
+            return Err(RequestResult{
+                typ: RequestResultType::ReadError,
+                v: Some(String::new()),
+                f: None,
+                m: None,
+            });
+        },
+    };
+    if let Err(e) = quota.check(identity, &usage, pointer, size) {
+        debug!("quota check failed for {}: {:?}", hex::encode(identity), e);
+        let typ = match e {
+            QuotaError::TooLarge => {
+                RequestResultType::TooLarge
+            },
+            QuotaError::Exceeded => {
+                RequestResultType::OverQuota
+            },
+        };
+        return Err(RequestResult{
+            typ,
+            v: Some(String::new()),
+            f: None,
+            m: None,
+        });
+    }
+    Ok(usage)
+}
+
+/// Inputs of a request other than its target, body and authentication.
+#[derive(Default)]
+pub struct RequestContext<'a> {
+    /// Sequence number of a mutable reference update.
+    pub sequence: Option<u64>,
+    /// Content metadata to store with an upload.
+    pub meta: ContentMeta,
+    /// Algorithm to store content with.
+    pub hash: Option<HashAlgo>,
+    /// Algorithm to derive mutable references with.
+    pub pointer_hash: Option<HashAlgo>,
+    /// Additional algorithms content is also stored under.
+    pub aliases: &'a [HashAlgo],
+    /// Storage limits of identities, unlimited if none.
+    pub quota: Option<&'a QuotaPolicy>,
+}
+
+pub fn process_method(method: &Method, url: String, mut f: impl Read, expected_size: usize, store: &dyn Store, auth_result: AuthResult, ctx: RequestContext) -> RequestResult {
+    let RequestContext { sequence, meta, hash, pointer_hash, aliases, quota } = ctx;
+    let unlimited = QuotaPolicy::default();
+    let quota = quota.unwrap_or(&unlimited);
     match method {
         Method::Put => {
             if !auth_result.valid() {
@@ -32,65 +270,117 @@ pub fn process_method(method: &Method, url: String, mut f: impl Read, expected_s
                     typ: RequestResultType::AuthError,
                     v: None,
                     f: None,
+                    m: None,
                 };
             }
             if auth_result.active() {
                 let res: RequestResult;
-                let rk = ResourceKey::from_str(url.as_str()).unwrap();
+                let sequence = match require_sequence(sequence) {
+                    Ok(v) => {
+                        v
+                    },
+                    Err(e) => {
+                        return e;
+                    },
+                };
+                let algo = hash.unwrap_or_default();
+                let rk = ResourceKey::with_algo(url.as_str(), pointer_hash.unwrap_or_default());
                 debug!("mutable put, authenticated as {:?} using mutable key {} -> {}", auth_result, &url, &rk);
                 let ptr = rk.pointer_for(&auth_result);
-                match put_mutable(ptr, path, f, expected_size) {
+                // rejected on the declared size before storing anything, and checked again with
+                // the size stored under the lock, which is not held while the content is written
+                if let Err(e) = check_quota(store, quota, &auth_result.identity, &ptr, expected_size as u64) {
+                    return e;
+                }
+                let record = match put_immutable(store, f, expected_size, algo, aliases, &meta) {
+                    Ok(v) => {
+                        v
+                    },
+                    Err(e) => {
+                        error!("mutable put failed: {:?}", e);
+                        return e;
+                    },
+                };
+                let _lock = quota_lock();
+                let mut usage = match check_quota(store, quota, &auth_result.identity, &ptr, record.size) {
                     Ok(v) => {
+                        v
+                    },
+                    Err(e) => {
+                        return e;
+                    },
+                };
+                match set_mutable(store, ptr.clone(), &record, &auth_result.signature, sequence) {
+                    Ok(v) => {
+                        usage.set(ptr, record.size);
+                        if let Err(e) = store.save_usage(&auth_result.identity, &usage) {
+                            error!("cannot save quota usage: {:?}", e);
+                        }
                         let digest_hex = hex::encode(v.digest);
                         res = RequestResult{
                             typ: RequestResultType::Changed,
                             v: Some(digest_hex),
                             f: None,
+                            m: None,
                         };
                     },
                     Err(e) => {
-                        let err_str = format!("{:?}", e);
-                        error!("{}", err_str);
-                        res = RequestResult {
-                            typ: RequestResultType::RecordError,
-                            v: Some(String::from(err_str)),
-                            f: None,
-                        };
+                        error!("mutable put failed: {:?}", e);
+                        res = e;
                     },
                 };
                 return res;
             } else {
                 debug!("immutable put");
                 let res: RequestResult;
-                match put_immutable(path, f, expected_size) {
+                let algo = match immutable_algo(&url, hash) {
                     Ok(v) => {
-                        let digest_hex = hex::encode(v.digest);
+                        v
+                    },
+                    Err(e) => {
+                        return RequestResult{
+                            typ: RequestResultType::InputError,
+                            v: Some(e),
+                            f: None,
+                            m: None,
+                        };
+                    },
+                };
+                match put_immutable(store, f, expected_size, algo, aliases, &meta) {
+                    Ok(v) => {
+                        let digest_hex = hex::encode(&v.digest);
                         res = RequestResult{
                             typ: RequestResultType::Changed,
                             v: Some(digest_hex),
                             f: None,
+                            m: Some(Box::new(RecordMeta{
+                                digest: v.digest,
+                                content: meta,
+                                sequence: 0,
+                            })),
                         };
                     },
                     Err(e) => {
-                        let err_str = format!("{}", e);
-                        res = RequestResult {
-                            typ: RequestResultType::RecordError,
-                            v: Some(String::from(err_str)),
-                            f: None,
-                        };
+                        error!("immutable put failed: {:?}", e);
+                        res = e;
                     },
                 };
                 return res;
             }
         },
-        Method::Get => {
-            let digest = match hex::decode(&url) {
+        Method::Get | Method::Head => {
+            if let Some((pointer_hex, rest)) = url.split_once('/') {
+                if HashAlgo::from_str(pointer_hex).is_err() {
+                    return process_history(pointer_hex, rest, store);
+                }
+            }
+            let digest = match record_name(&url) {
                 Err(e) => {
-                    let err_str = format!("{}", e);
                     return RequestResult {
                         typ: RequestResultType::InputError,
-                        v: Some(String::from(err_str)),
+                        v: Some(e),
                         f: None,
+                        m: None,
                     };
                 },
                 Ok(v) => {
@@ -98,15 +388,24 @@ pub fn process_method(method: &Method, url: String, mut f: impl Read, expected_s
                 },
             };
 
-            let full_path_buf = path.join(&url);
-            debug!("url {} resolved to {:?}", &url, &full_path_buf);
+            if is_deleted(store, &digest) {
+                debug!("mutable reference {} has been deleted", &url);
+                return RequestResult {
+                    typ: RequestResultType::Gone,
+                    v: Some(String::new()),
+                    f: None,
+                    m: None,
+                };
+            }
 
-            match get_record(digest, full_path_buf.as_path()) {
-                Some(v) => {
+            match get_record(store, &digest) {
+                Some((f, m)) => {
+                    debug!("url {} resolved to {}", &url, hex::encode(&m.digest));
                     return RequestResult {
                         typ: RequestResultType::Found,
                         v: None, //Some(String::new()),
-                        f: Some(v),
+                        f: Some(f),
+                        m: Some(Box::new(m)),
                     };
                 },
                 None => {
@@ -115,8 +414,56 @@ pub fn process_method(method: &Method, url: String, mut f: impl Read, expected_s
                         typ: RequestResultType::RecordError,
                         v: Some(String::new()),
                         f: None,
+                        m: None,
+                    };
+                },
+            };
+        },
+        Method::Delete => {
+            if !auth_result.valid() || !auth_result.active() {
+                return RequestResult{
+                    typ: RequestResultType::AuthError,
+                    v: None,
+                    f: None,
+                    m: None,
+                };
+            }
+            let sequence = match require_sequence(sequence) {
+                Ok(v) => {
+                    v
+                },
+                Err(e) => {
+                    return e;
+                },
+            };
+            let rk = ResourceKey::with_algo(url.as_str(), pointer_hash.unwrap_or_default());
+            debug!("mutable delete, authenticated as {:?} using mutable key {} -> {}", auth_result, &url, &rk);
+            let ptr = rk.pointer_for(&auth_result);
+            let _lock = quota_lock();
+            match delete_mutable(store, ptr.clone(), &auth_result.signature, sequence) {
+                Ok(v) => {
+                    match store.load_usage(&auth_result.identity) {
+                        Ok(mut usage) => {
+                            usage.remove(&ptr);
+                            if let Err(e) = store.save_usage(&auth_result.identity, &usage) {
+                                error!("cannot save quota usage: {:?}", e);
+                            }
+                        },
+                        Err(e) => {
+                            error!("cannot load quota usage: {:?}", e);
+                        },
+                    };
+                    return RequestResult{
+                        typ: RequestResultType::Changed,
+                        v: Some(hex::encode(v.digest)),
+                        f: None,
+                        m: None,
                     };
                 },
+                Err(e) => {
+                    debug!("mutable delete failed: {:?}", e);
+                    return e;
+                },
             };
         },
         _ => {},
@@ -125,6 +472,7 @@ pub fn process_method(method: &Method, url: String, mut f: impl Read, expected_s
         typ: RequestResultType::InputError,
         v: Some(String::new()),
         f: None,
+        m: None,
     }
 }
 
@@ -132,133 +480,402 @@ pub fn process_method(method: &Method, url: String, mut f: impl Read, expected_s
 mod tests {
     use tempfile::tempdir;
     use tiny_http::Method;
-    use super::process_method;
-    use std::fs::{
-        read,
-        write,
-        File,
+    use super::{
+        process_method,
+        RequestContext,
+    };
+    use std::io::{
+        empty,
+        read_to_string,
+        Read,
+    };
+    use std::sync::mpsc::{
+        channel,
+        Receiver,
+        Sender,
     };
-    use std::path::Path;
+    use std::thread;
+    use std::time::Duration;
+    use crate::hash::HashAlgo;
     use crate::auth::AuthResult;
-    use crate::record::RequestResultType;
-    use env_logger;
+    use crate::record::{
+        put_immutable,
+        RequestResultType,
+    };
+    use crate::store::fs::FsStore;
+    use crate::store::mem::MemStore;
+    use crate::meta::ContentMeta;
+    use crate::quota::{
+        Limits,
+        QuotaPolicy,
+    };
+
+    fn auth(identity: &[u8]) -> AuthResult {
+        AuthResult {
+            identity: identity.to_vec(),
+            error: false,
+            signature: String::new(),
+        }
+    }
+
+    /// Body that is held up until the test lets it through, to keep an upload in progress.
+    struct HeldBody {
+        reading: Sender<()>,
+        release: Receiver<()>,
+        released: Option<bool>,
+        data: &'static [u8],
+    }
+
+    impl Read for HeldBody {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.released.is_none() {
+                let _ = self.reading.send(());
+                self.released = Some(self.release.recv_timeout(Duration::from_secs(10)).is_ok());
+            }
+            self.data.read(buf)
+        }
+    }
 
 
     #[test]
     fn test_get_ok() {
-        let d = tempdir().unwrap();
-        let url = String::from("deadbeef");
+        let store = MemStore::default();
+        let url = String::from("c3ab8ff13720e8ad9047dd39466b3c8974e592c2fa383d4a3960714caef0c4f2");
         let data = "foobar";
-
-        let fp = d.path().join(&url); //.as_path().to_string().unwrap();
-        write(&fp, data);
-        let f = File::open(&fp).unwrap();
+        put_immutable(&store, data.as_bytes(), 6, HashAlgo::Sha256, &[], &ContentMeta::default()).unwrap();
 
         let method = Method::Get;
 
-        let auth = AuthResult {
-            identity: vec!(),
-            error: false,
-        };
+        let res = process_method(&method, url, empty(), 0, &store, auth(&[]), RequestContext::default());
+        assert_eq!(res.typ, RequestResultType::Found);
+        assert_eq!(read_to_string(res.f.unwrap()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_head_ok() {
+        let store = MemStore::default();
+        let url = String::from("c3ab8ff13720e8ad9047dd39466b3c8974e592c2fa383d4a3960714caef0c4f2");
+        let data = "foobar";
+        put_immutable(&store, data.as_bytes(), 6, HashAlgo::Sha256, &[], &ContentMeta::default()).unwrap();
 
-        let res = process_method(&method, url, f, 6, &d.path(), auth);
+        let method = Method::Head;
+
+        let res = process_method(&method, url.clone(), empty(), 0, &store, auth(&[]), RequestContext::default());
         assert_eq!(res.typ, RequestResultType::Found);
+        assert_eq!(res.f.unwrap().size().unwrap(), 6);
+        assert_eq!(hex::encode(res.m.unwrap().digest), url);
     }
 
     #[test]
     fn test_get_bogus() {
-        let d = tempdir().unwrap();
+        let store = MemStore::default();
         let url = String::from("teadbeef");
-        let data = "foobar";
-
-        let fp = d.path().join(&url); //.as_path().to_string().unwrap();
-        write(&fp, data);
-        let f = File::open(&fp).unwrap();
 
         let method = Method::Get;
 
-        let auth = AuthResult {
-            identity: vec!(),
-            error: false,
-        };
-
-        let res = process_method(&method, url, f, 6, &d.path(), auth);
+        let res = process_method(&method, url, empty(), 0, &store, auth(&[]), RequestContext::default());
         assert_eq!(res.typ, RequestResultType::InputError);
     }
 
     #[test]
     fn test_put_immutable() {
-        let d = tempdir().unwrap();
-        let mut url = String::from("deadbeef");
+        let store = MemStore::default();
         let data = "foobar";
 
-        let fp = d.path().join(&url); //.as_path().to_string().unwrap();
-        write(&fp, data);
-        let f = File::open(&fp).unwrap();
-
         let method = Method::Put;
 
-        let auth = AuthResult {
-            identity: vec!(),
-            error: false,
-        };
-    
-        url = String::new();
-        let res = process_method(&method, url, f, 6, &d.path(), auth);
+        let res = process_method(&method, String::new(), data.as_bytes(), 6, &store, auth(&[]), RequestContext::default());
         assert_eq!(res.typ, RequestResultType::Changed);
 
         let content_ref = String::from("c3ab8ff13720e8ad9047dd39466b3c8974e592c2fa383d4a3960714caef0c4f2");
+        assert_eq!(hex::encode(res.m.unwrap().digest), content_ref);
         assert_eq!(res.v.unwrap(), content_ref);
 
     }
 
     #[test]
-    fn test_put_mutable() {
+    fn test_get_cid() {
+        let store = MemStore::default();
+        put_immutable(&store, &b"foo"[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default()).unwrap();
+
+        let res = process_method(&Method::Get, String::from("bafkreibme22gw2h7y2h7tg2fhqotaqjucnbc24deqo72b6mkl2egezxhvy"), empty(), 0, &store, auth(&[]), RequestContext::default());
+        assert_eq!(res.typ, RequestResultType::Found);
+        assert_eq!(read_to_string(res.f.unwrap()).unwrap(), "foo");
+
+        let res = process_method(&Method::Get, String::from("bafkreibme22gw2h7y2h7tg2fhqotaqjucnbc24deqo72b6mkl2egezxhv"), empty(), 0, &store, auth(&[]), RequestContext::default());
+        assert_eq!(res.typ, RequestResultType::InputError);
+    }
+
+    #[test]
+    fn test_put_immutable_algo() {
+        let store = MemStore::default();
+        let data = "foo";
+        let blake3_hex = "04e0bb39f30b1a3feb89f536c93be15055482df748674b00d26e5a75777702e9";
+
+        let res = process_method(&Method::Put, String::from("blake3"), data.as_bytes(), 3, &store, auth(&[]), RequestContext::default());
+        assert_eq!(res.typ, RequestResultType::Changed);
+        let name = res.v.unwrap();
+        assert_eq!(name, format!("1e20{}", blake3_hex));
+
+        let res = process_method(&Method::Put, String::new(), data.as_bytes(), 3, &store, auth(&[]), RequestContext { hash: Some(HashAlgo::Sha512), ..Default::default() });
+        assert!(res.v.unwrap().starts_with("1340f7fbba6e"));
+
+        let res = process_method(&Method::Put, String::from("sha256"), data.as_bytes(), 3, &store, auth(&[]), RequestContext { hash: Some(HashAlgo::Blake3), ..Default::default() });
+        assert_eq!(res.typ, RequestResultType::InputError);
+
+        // by name, and by algorithm and bare digest
+        for url in [name, format!("blake3/{}", blake3_hex)] {
+            let res = process_method(&Method::Get, url, empty(), 0, &store, auth(&[]), RequestContext::default());
+            assert_eq!(res.typ, RequestResultType::Found);
+            assert_eq!(read_to_string(res.f.unwrap()).unwrap(), data);
+        }
+
+        // the same content under another algorithm is a different record
+        let res = process_method(&Method::Get, format!("sha256/{}", blake3_hex), empty(), 0, &store, auth(&[]), RequestContext::default());
+        assert_eq!(res.typ, RequestResultType::RecordError);
+
+        let res = process_method(&Method::Get, format!("sha512/{}", blake3_hex), empty(), 0, &store, auth(&[]), RequestContext::default());
+        assert_eq!(res.typ, RequestResultType::InputError);
+    }
+
+    #[test]
+    fn test_put_keccak() {
+        let store = MemStore::default();
+        let keccak_hex = "41b1a0649752af1b28b3dc29a1556eee781e4a4c3a1f7f53f90fa834de098c4d";
+
+        let res = process_method(&Method::Put, String::new(), &b"foo"[..], 3, &store, auth(&[]), RequestContext { aliases: &[HashAlgo::Keccak256], ..Default::default() });
+        assert_eq!(res.v.unwrap(), "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae");
+
+        let res = process_method(&Method::Get, format!("keccak256/{}", keccak_hex), empty(), 0, &store, auth(&[]), RequestContext::default());
+        assert_eq!(res.typ, RequestResultType::Found);
+        assert_eq!(hex::encode(res.m.unwrap().digest), "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae");
+
+        // mutable references are derived with the pointer algorithm, whatever the content is
+        // stored with
+        let identity = hex::decode("00000000000000000000000000000000deadbeef").unwrap();
+        let keccak_pointer = "cc00885d367985a110292614f660920eafddb1029b80140d58584e9e1f6c0d67";
+        let res = process_method(&Method::Put, String::from("foo"), &b"bar"[..], 3, &store, auth(&identity), RequestContext { sequence: Some(1), pointer_hash: Some(HashAlgo::Keccak256), ..Default::default() });
+        assert_eq!(res.v.unwrap(), keccak_pointer);
+        let res = process_method(&Method::Put, String::from("foo"), &b"bar"[..], 3, &store, auth(&identity), RequestContext { sequence: Some(1), hash: Some(HashAlgo::Keccak256), ..Default::default() });
+        assert_ne!(res.v.unwrap(), keccak_pointer);
+
+        let res = process_method(&Method::Delete, String::from("foo"), empty(), 0, &store, auth(&identity), RequestContext { sequence: Some(2), pointer_hash: Some(HashAlgo::Keccak256), ..Default::default() });
+        assert_eq!(res.typ, RequestResultType::Changed);
+        assert_eq!(res.v.unwrap(), keccak_pointer);
+        let res = process_method(&Method::Get, String::from(keccak_pointer), empty(), 0, &store, auth(&[]), RequestContext::default());
+        assert_eq!(res.typ, RequestResultType::Gone);
+    }
+
+    #[test]
+    fn test_put_immutable_unwritable() {
         let d = tempdir().unwrap();
-        let url = String::from("deadbeef");
         let data = "foobar";
-
-        let fp = d.path().join(&url); //.as_path().to_string().unwrap();
-        write(&fp, data);
-        let f = File::open(&fp).unwrap();
+        let store = FsStore::new(&d.path().join("missing"));
 
         let method = Method::Put;
 
-        let auth = AuthResult {
-            identity: vec!(0x66, 0x6f, 0x6f),
-            error: false,
-        };
+        let res = process_method(&method, String::new(), data.as_bytes(), 6, &store, auth(&[]), RequestContext::default());
+        assert_eq!(res.typ, RequestResultType::WriteError);
+    }
+
+    #[test]
+    fn test_put_mutable() {
+        let store = MemStore::default();
+        let url = String::from("deadbeef");
+        let data = "foobar";
 
-        let res = process_method(&method, url, f, 6, &d.path(), auth);
+        let method = Method::Put;
+
+        let res = process_method(&method, url, data.as_bytes(), 6, &store, auth(b"foo"), RequestContext { sequence: Some(1), ..Default::default() });
         assert_eq!(res.typ, RequestResultType::Changed);
 
         let content_ref = String::from("129208a8eac1bedd060645411baaae4aabc5d9e4c858942defe139b5ba15aba6");
         assert_eq!(res.v.unwrap(), content_ref);
 
-        let fp_immutable = d.path().join(&content_ref);
-        let r = read(fp_immutable).unwrap();
+        let res = process_method(&Method::Get, content_ref, empty(), 0, &store, auth(&[]), RequestContext::default());
+        assert_eq!(read_to_string(res.f.unwrap()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_mutable_history() {
+        let store = MemStore::default();
+        let url = String::from("deadbeef");
+        let pointer_hex = "129208a8eac1bedd060645411baaae4aabc5d9e4c858942defe139b5ba15aba6";
+
+        for (sequence, data, sig) in [(1, "foo", "foosig"), (2, "bar", "barsig")] {
+            let res = process_method(&Method::Put, url.clone(), data.as_bytes(), 3, &store, AuthResult { signature: String::from(sig), ..auth(b"foo") }, RequestContext { sequence: Some(sequence), ..Default::default() });
+            assert_eq!(res.typ, RequestResultType::Changed);
+            assert_eq!(res.v.unwrap(), pointer_hex);
+        }
+
+        let res = process_method(&Method::Get, format!("{}/history", pointer_hex), empty(), 0, &store, auth(&[]), RequestContext::default());
+        assert_eq!(res.typ, RequestResultType::Found);
+        let listing = res.v.unwrap();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("0 2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae "));
+        assert!(lines[0].ends_with(" foosig 1"));
+        assert!(lines[1].starts_with("1 fcde2b2edba56bf408601fb721fe9b5c338d10ee429ea04fae5511b68fbf8fb9 "));
+
+        let res = process_method(&Method::Get, format!("{}/history/0", pointer_hex), empty(), 0, &store, auth(&[]), RequestContext::default());
+        assert_eq!(res.typ, RequestResultType::Found);
+        assert_eq!(read_to_string(res.f.unwrap()).unwrap(), "foo");
 
-        assert_eq!(data.as_bytes(), r);
+        let res = process_method(&Method::Get, format!("{}/history/2", pointer_hex), empty(), 0, &store, auth(&[]), RequestContext::default());
+        assert_eq!(res.typ, RequestResultType::RecordError);
     }
 
     #[test]
-    fn test_put_mutable_noauth() {
-        let d = tempdir().unwrap();
+    fn test_delete_mutable() {
+        let store = MemStore::default();
         let url = String::from("deadbeef");
-        let data = "foobar";
+        let pointer_hex = String::from("129208a8eac1bedd060645411baaae4aabc5d9e4c858942defe139b5ba15aba6");
+        let content_ref = String::from("c3ab8ff13720e8ad9047dd39466b3c8974e592c2fa383d4a3960714caef0c4f2");
 
-        let fp = d.path().join(&url); //.as_path().to_string().unwrap();
-        write(&fp, data);
-        let f = File::open(&fp).unwrap();
+        let res = process_method(&Method::Put, url.clone(), "foobar".as_bytes(), 6, &store, auth(b"foo"), RequestContext { sequence: Some(1), ..Default::default() });
+        assert_eq!(res.typ, RequestResultType::Changed);
 
-        let method = Method::Put;
+        let res = process_method(&Method::Delete, url.clone(), empty(), 0, &store, auth(&[]), RequestContext::default());
+        assert_eq!(res.typ, RequestResultType::AuthError);
+
+        let res = process_method(&Method::Delete, url.clone(), empty(), 0, &store, auth(b"foo"), RequestContext { sequence: Some(2), ..Default::default() });
+        assert_eq!(res.typ, RequestResultType::Changed);
+        assert_eq!(res.v.unwrap(), pointer_hex);
+
+        let res = process_method(&Method::Get, pointer_hex.clone(), empty(), 0, &store, auth(&[]), RequestContext::default());
+        assert_eq!(res.typ, RequestResultType::Gone);
+
+        let res = process_method(&Method::Get, content_ref, empty(), 0, &store, auth(&[]), RequestContext::default());
+        assert_eq!(res.typ, RequestResultType::Found);
+
+        let res = process_method(&Method::Delete, url, empty(), 0, &store, auth(b"foo"), RequestContext { sequence: Some(3), ..Default::default() });
+        assert_eq!(res.typ, RequestResultType::RecordError);
+    }
 
-        let auth = AuthResult {
-            identity: vec!(0x2a),
-            error: true,
+    #[test]
+    fn test_put_mutable_quota() {
+        let store = MemStore::default();
+        let quota = QuotaPolicy {
+            default: Limits {
+                bytes: Some(6),
+                objects: Some(1),
+            },
+            ..Default::default()
+        };
+
+        for (sequence, url, data, typ) in [
+            (1, "deadbeef", "foobar", RequestResultType::Changed),
+            (2, "deadbeef", "foobarbaz", RequestResultType::TooLarge),
+            (1, "beeffeed", "x", RequestResultType::OverQuota),
+            (2, "deadbeef", "foo", RequestResultType::Changed),
+        ] {
+            let res = process_method(&Method::Put, String::from(url), data.as_bytes(), data.len(), &store, auth(b"foo"), RequestContext { sequence: Some(sequence), quota: Some(&quota), ..Default::default() });
+            assert_eq!(res.typ, typ);
+        }
+
+        let res = process_method(&Method::Delete, String::from("deadbeef"), empty(), 0, &store, auth(b"foo"), RequestContext { sequence: Some(3), quota: Some(&quota), ..Default::default() });
+        assert_eq!(res.typ, RequestResultType::Changed);
+
+        let res = process_method(&Method::Put, String::from("beeffeed"), "x".as_bytes(), 1, &store, auth(b"foo"), RequestContext { sequence: Some(1), quota: Some(&quota), ..Default::default() });
+        assert_eq!(res.typ, RequestResultType::Changed);
+    }
+
+    #[test]
+    fn test_put_mutable_quota_unknown_size() {
+        let store = MemStore::default();
+        let quota = QuotaPolicy {
+            default: Limits {
+                bytes: Some(6),
+                objects: None,
+            },
+            ..Default::default()
         };
 
-        let res = process_method(&method, url, f, 6, &d.path(), auth);
+        // without a declared size, the size stored is checked
+        let res = process_method(&Method::Put, String::from("deadbeef"), &b"foobarbaz"[..], 0, &store, auth(b"foo"), RequestContext { sequence: Some(1), quota: Some(&quota), ..Default::default() });
+        assert_eq!(res.typ, RequestResultType::TooLarge);
+        let res = process_method(&Method::Put, String::from("deadbeef"), &b"foobar"[..], 0, &store, auth(b"foo"), RequestContext { sequence: Some(1), quota: Some(&quota), ..Default::default() });
+        assert_eq!(res.typ, RequestResultType::Changed);
+        let res = process_method(&Method::Put, String::from("beeffeed"), &b"x"[..], 0, &store, auth(b"foo"), RequestContext { sequence: Some(1), quota: Some(&quota), ..Default::default() });
+        assert_eq!(res.typ, RequestResultType::OverQuota);
+    }
+
+    #[test]
+    fn test_put_mutable_concurrent() {
+        let store = MemStore::default();
+        let (reading_tx, reading) = channel();
+        let (release, release_rx) = channel();
+
+        thread::scope(|scope| {
+            let held = scope.spawn(|| {
+                let mut body = HeldBody {
+                    reading: reading_tx,
+                    release: release_rx,
+                    released: None,
+                    data: b"foo",
+                };
+                let res = process_method(&Method::Put, String::from("deadbeef"), &mut body, 3, &store, auth(b"foo"), RequestContext { sequence: Some(1), ..Default::default() });
+                (res.typ, body.released)
+            });
+
+            // an upload in progress does not hold up updates by others
+            reading.recv().unwrap();
+            let res = process_method(&Method::Put, String::from("deadbeef"), &b"bar"[..], 3, &store, auth(b"bar"), RequestContext { sequence: Some(1), ..Default::default() });
+            assert_eq!(res.typ, RequestResultType::Changed);
+            let _ = release.send(());
+
+            let (typ, released) = held.join().unwrap();
+            assert_eq!(typ, RequestResultType::Changed);
+            assert_eq!(released, Some(true));
+        });
+    }
+
+    #[test]
+    fn test_put_mutable_sequence() {
+        let store = MemStore::default();
+        let url = String::from("deadbeef");
+        let pointer_hex = "129208a8eac1bedd060645411baaae4aabc5d9e4c858942defe139b5ba15aba6";
+
+        let res = process_method(&Method::Put, url.clone(), &b"foo"[..], 3, &store, auth(b"foo"), RequestContext::default());
+        assert_eq!(res.typ, RequestResultType::InputError);
+
+        for (sequence, data, typ) in [
+            (5, "foo", RequestResultType::Changed),
+            (5, "bar", RequestResultType::Conflict),
+            (3, "bar", RequestResultType::Conflict),
+            (6, "bar", RequestResultType::Changed),
+        ] {
+            let res = process_method(&Method::Put, url.clone(), data.as_bytes(), 3, &store, auth(b"foo"), RequestContext { sequence: Some(sequence), ..Default::default() });
+            assert_eq!(res.typ, typ);
+        }
+
+        let res = process_method(&Method::Get, String::from(pointer_hex), empty(), 0, &store, auth(&[]), RequestContext::default());
+        assert_eq!(res.m.unwrap().sequence, 6);
+        assert_eq!(read_to_string(res.f.unwrap()).unwrap(), "bar");
+
+        // records fetched by digest have no sequence
+        let res = process_method(&Method::Get, String::from("fcde2b2edba56bf408601fb721fe9b5c338d10ee429ea04fae5511b68fbf8fb9"), empty(), 0, &store, auth(&[]), RequestContext::default());
+        assert_eq!(res.m.unwrap().sequence, 0);
+
+        let res = process_method(&Method::Delete, url.clone(), empty(), 0, &store, auth(b"foo"), RequestContext { sequence: Some(6), ..Default::default() });
+        assert_eq!(res.typ, RequestResultType::Conflict);
+        let res = process_method(&Method::Delete, url.clone(), empty(), 0, &store, auth(b"foo"), RequestContext { sequence: Some(7), ..Default::default() });
+        assert_eq!(res.typ, RequestResultType::Changed);
+
+        // a deleted reference cannot be brought back by replaying an earlier update
+        let res = process_method(&Method::Put, url, &b"foo"[..], 3, &store, auth(b"foo"), RequestContext { sequence: Some(5), ..Default::default() });
+        assert_eq!(res.typ, RequestResultType::Conflict);
+    }
+
+    #[test]
+    fn test_put_mutable_noauth() {
+        let store = MemStore::default();
+        let url = String::from("deadbeef");
+        let data = "foobar";
+
+        let method = Method::Put;
+
+        let res = process_method(&method, url, data.as_bytes(), 6, &store, AuthResult { error: true, ..auth(&[0x2a]) }, RequestContext::default());
         assert_eq!(res.typ, RequestResultType::AuthError);
     }
 }
@@ -0,0 +1,837 @@
+use std::fmt;
+use std::fs::{
+    create_dir,
+    read_dir,
+    read_link,
+    read_to_string,
+    DirEntry,
+    File,
+    rename,
+    remove_file,
+};
+use std::io;
+use std::io::{
+    Read,
+    Write,
+};
+use std::str::FromStr;
+use std::os::unix::fs::symlink;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::process;
+use std::sync::Mutex;
+use std::sync::atomic::{
+    AtomicUsize,
+    Ordering,
+};
+
+use log::{debug, info, warn};
+use tempfile::NamedTempFile;
+
+use crate::hash::{
+    parse_name,
+    HashAlgo,
+};
+use crate::history::{
+    append as history_append,
+    list as history_list,
+    Revision,
+};
+use crate::meta::{
+    ContentMeta,
+    read_meta,
+    write_meta,
+};
+use crate::quota::{
+    load_usage,
+    save_usage,
+    Usage,
+};
+use crate::record::{
+    persist,
+    persist_new,
+    write_error,
+    RecordMeta,
+    RequestResult,
+    RequestResultType,
+};
+use crate::store::{
+    check_sequence,
+    stage_blob,
+    RecordReader,
+    Store,
+};
+
+static LINK_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+
+/// Point a mutable reference at a record, replacing any previous target.
+///
+/// The new link is created under a temporary name and renamed over the old one, so readers see
+/// either the previous or the new target but never a missing link.
+pub fn set_link(target: &Path, link_path: &Path) -> io::Result<()> {
+    let link_name = match link_path.file_name() {
+        Some(v) => {
+            v.to_string_lossy()
+        },
+        None => {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "link path has no file name"));
+        },
+    };
+    let seq = LINK_SEQ.fetch_add(1, Ordering::Relaxed);
+    let tmp_name = format!(".{}.{}.{}.tmp", link_name, process::id(), seq);
+    let tmp_path = link_path.with_file_name(tmp_name);
+
+    symlink(target, &tmp_path)?;
+    if let Err(e) = rename(&tmp_path, link_path) {
+        let _ = remove_file(&tmp_path);
+        return Err(e);
+    }
+    if let Some(dir) = link_path.parent() {
+        if !dir.as_os_str().is_empty() {
+            File::open(dir)?.sync_all()?;
+        }
+    }
+    Ok(())
+}
+
+/// Follow a record path to the immutable record it refers to.
+///
+/// Immutable records resolve to themselves, mutable references to the record their link targets.
+pub fn resolve_path(path: &Path) -> Option<RecordMeta> {
+    let path_canon = match path.canonicalize() {
+        Ok(v) => {
+            v
+        },
+        Err(_) => {
+            return None;
+        },
+    };
+    let digest = match path_canon.file_name() {
+        Some(v) => {
+            match hex::decode(v.to_string_lossy().as_bytes()) {
+                Ok(v) => {
+                    v
+                },
+                Err(_) => {
+                    return None;
+                },
+            }
+        },
+        None => {
+            return None;
+        },
+    };
+    let content = read_meta(&path_canon);
+    Some(RecordMeta{
+        digest,
+        content,
+        sequence: 0,
+    })
+}
+
+
+const LAYOUT_MARKER: &str = ".layout";
+
+/// How records are spread over directories.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    /// All entries in the store directory itself.
+    Flat,
+    /// Entries fanned out over two levels of subdirectories named after the first two bytes of
+    /// their hex name, as in `ab/cd/abcd...`.
+    Sharded,
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Layout, String> {
+        match s {
+            "flat" => {
+                Ok(Layout::Flat)
+            },
+            "sharded" => {
+                Ok(Layout::Sharded)
+            },
+            _ => {
+                Err(format!("unknown layout '{}'", s))
+            },
+        }
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Layout::Flat => {
+                fmt.write_str("flat")
+            },
+            Layout::Sharded => {
+                fmt.write_str("sharded")
+            },
+        }
+    }
+}
+
+/// Read the layout marker of a store, if it has one.
+pub fn read_layout(path: &Path) -> io::Result<Option<Layout>> {
+    match read_to_string(path.join(LAYOUT_MARKER)) {
+        Ok(v) => {
+            match Layout::from_str(v.trim()) {
+                Ok(v) => {
+                    Ok(Some(v))
+                },
+                Err(e) => {
+                    Err(io::Error::new(io::ErrorKind::InvalidData, e))
+                },
+            }
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            Ok(None)
+        },
+        Err(e) => {
+            Err(e)
+        },
+    }
+}
+
+fn write_layout(path: &Path, layout: Layout) -> io::Result<()> {
+    let mut tmp = NamedTempFile::new_in(path)?;
+    tmp.write_all(format!("{}\n", layout).as_bytes())?;
+    persist(tmp, &path.join(LAYOUT_MARKER))
+}
+
+/// Whether the store directory itself holds records or mutable references.
+fn has_flat_entries(path: &Path) -> io::Result<bool> {
+    for entry in read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry_stem(&name).is_some() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Hex name an entry belongs to, for records, mutable references and their sidecar files.
+fn entry_stem(name: &str) -> Option<&str> {
+    let stem = name.split('.').next()?;
+    if stem.is_empty() || hex::decode(stem).is_err() {
+        return None;
+    }
+    Some(stem)
+}
+
+/// Create the directory an entry goes into, syncing each new level to its parent.
+fn prepare_dir(entry_path: &Path) -> io::Result<()> {
+    let dir = match entry_path.parent() {
+        Some(v) => {
+            v
+        },
+        None => {
+            return Ok(());
+        },
+    };
+    if dir.as_os_str().is_empty() || dir.is_dir() {
+        return Ok(());
+    }
+    prepare_dir(dir)?;
+    create_dir(dir).or_else(|e| if e.kind() == io::ErrorKind::AlreadyExists { Ok(()) } else { Err(e) })?;
+    if let Some(parent) = dir.parent() {
+        if !parent.as_os_str().is_empty() {
+            File::open(parent)?.sync_all()?;
+        }
+    }
+    Ok(())
+}
+
+
+/// Records in a directory, named by the hex of their digest.
+///
+/// Mutable references are symlinks to the record they point to, with their revision log next to
+/// them.
+pub struct FsStore {
+    path: PathBuf,
+    layout: Layout,
+    /// Serializes mutable reference updates, so that the revision log order matches link order.
+    lock: Mutex<()>,
+}
+
+impl FsStore {
+    /// Flat store without a layout check, for tests.
+    #[cfg(test)]
+    pub fn new(path: &Path) -> FsStore {
+        FsStore::with_layout(path, Layout::Flat)
+    }
+
+    pub fn with_layout(path: &Path, layout: Layout) -> FsStore {
+        FsStore {
+            path: path.to_path_buf(),
+            layout,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Open the store in a directory, checking its layout marker.
+    ///
+    /// A store without a marker is given one, with the requested layout or flat by default. A
+    /// store that already has a marker, or that already holds flat entries, must match the
+    /// requested layout.
+    pub fn open(path: &Path, requested: Option<Layout>) -> io::Result<FsStore> {
+        // links target records by absolute path, which must not depend on the link directory
+        let path = &path.canonicalize()?;
+        let layout = match read_layout(path)? {
+            Some(v) => {
+                v
+            },
+            None if requested == Some(Layout::Sharded) && has_flat_entries(path)? => {
+                Layout::Flat
+            },
+            None => {
+                let v = requested.unwrap_or(Layout::Flat);
+                write_layout(path, v)?;
+                v
+            },
+        };
+        if let Some(v) = requested {
+            if v != layout {
+                let msg = format!("store in {:?} has {} layout, not {}", path, layout, v);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+            }
+        }
+        info!("store in {:?} has {} layout", path, layout);
+        Ok(FsStore::with_layout(path, layout))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Location of a record, mutable reference or sidecar file with the given name.
+    ///
+    /// Sharding goes by the digest, skipping the algorithm prefix of multihash names.
+    pub fn entry_path(&self, name: &str) -> PathBuf {
+        let stem = entry_stem(name).unwrap_or(name);
+        let offset = match hex::decode(stem).ok().as_deref().and_then(parse_name) {
+            Some((algo, _)) if algo != HashAlgo::Sha256 => {
+                4
+            },
+            _ => {
+                0
+            },
+        };
+        match (self.layout, name.get(offset..offset + 2), name.get(offset + 2..offset + 4)) {
+            (Layout::Sharded, Some(a), Some(b)) => {
+                self.path.join(a).join(b).join(name)
+            },
+            _ => {
+                self.path.join(name)
+            },
+        }
+    }
+
+    /// All entries in the directories records are kept in, including the store directory itself.
+    pub fn entries(&self) -> io::Result<Vec<DirEntry>> {
+        let mut r = vec!();
+        for entry in read_dir(&self.path)? {
+            let entry = entry?;
+            if self.layout == Layout::Sharded && entry.file_type()?.is_dir() && entry.file_name().len() == 2 {
+                for sub in read_dir(entry.path())? {
+                    let sub = sub?;
+                    if sub.file_type()?.is_dir() {
+                        for v in read_dir(sub.path())? {
+                            r.push(v?);
+                        }
+                    }
+                }
+                continue;
+            }
+            r.push(entry);
+        }
+        Ok(r)
+    }
+}
+
+/// Outcome of migrating a flat store to the sharded layout.
+#[derive(Debug, Default)]
+pub struct MigrateReport {
+    /// Records and sidecar files moved.
+    pub moved: usize,
+    /// Mutable references linked anew in their shard.
+    pub relinked: usize,
+}
+
+/// Convert a flat store to the sharded layout in place.
+///
+/// Entries are moved one at a time and the layout marker is only written at the end, so an
+/// interrupted migration can simply be run again. The server must not run meanwhile.
+pub fn migrate(path: &Path) -> io::Result<MigrateReport> {
+    let mut report = MigrateReport::default();
+    let path = &path.canonicalize()?;
+    if read_layout(path)? == Some(Layout::Sharded) {
+        return Ok(report);
+    }
+    let store = FsStore::with_layout(path, Layout::Sharded);
+    for entry in read_dir(path)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type()?.is_dir() || entry_stem(&name).is_none() {
+            continue;
+        }
+        let target_path = store.entry_path(&name);
+        prepare_dir(&target_path)?;
+        if entry.file_type()?.is_symlink() {
+            let target = read_link(entry.path())?;
+            let digest_hex = match target.file_name().map(|v| v.to_string_lossy().to_string()) {
+                Some(v) if hex::decode(&v).is_ok() => {
+                    v
+                },
+                _ => {
+                    warn!("leaving link {} with foreign target {:?} in place", name, target);
+                    continue;
+                },
+            };
+            set_link(&store.entry_path(&digest_hex), &target_path)?;
+            remove_file(entry.path())?;
+            report.relinked += 1;
+        } else {
+            rename(entry.path(), &target_path)?;
+            report.moved += 1;
+        }
+        debug!("migrated {}", name);
+    }
+    File::open(path)?.sync_all()?;
+    write_layout(path, Layout::Sharded)?;
+    Ok(report)
+}
+
+/// Whether a record is already stored at `path`, refusing the name if a mutable reference or an
+/// alias has it.
+fn record_exists(path: &Path) -> Result<bool, RequestResult> {
+    match path.symlink_metadata() {
+        Ok(v) if v.file_type().is_symlink() => {
+            warn!("refusing to store record over link {:?}", path);
+            Err(RequestResult{
+                typ: RequestResultType::WriteError,
+                v: None,
+                f: None,
+                m: None,
+            })
+        },
+        Ok(_) => {
+            Ok(true)
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            Ok(false)
+        },
+        Err(e) => {
+            Err(write_error(e, "cannot check record"))
+        },
+    }
+}
+
+impl Store for FsStore {
+    fn put_blob(&self, f: &mut dyn Read, expected_size: usize, algo: HashAlgo, meta: &ContentMeta) -> Result<Vec<u8>, RequestResult> {
+        // staged inside the store so that the final rename never crosses filesystems
+        let (tempfile, z) = stage_blob(f, expected_size, algo, &self.path)?;
+        let hash = hex::encode(&z);
+
+        let final_path_buf = self.entry_path(&hash);
+        let final_path = final_path_buf.as_path();
+        if record_exists(final_path)? {
+            debug!("record {} already stored, keeping its metadata", hash);
+            return Ok(z);
+        }
+        match prepare_dir(final_path).and_then(|_| persist_new(tempfile, final_path)) {
+            Ok(()) => {},
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                // stored concurrently since checked, or taken by a link in the meantime
+                record_exists(final_path)?;
+                debug!("record {} already stored, keeping its metadata", hash);
+                return Ok(z);
+            },
+            Err(e) => {
+                return Err(write_error(e, "cannot store record"));
+            },
+        };
+
+        if let Err(e) = write_meta(final_path, meta) {
+            return Err(write_error(e, "cannot write metadata"));
+        }
+
+        Ok(z)
+    }
+
+    fn set_pointer(&self, pointer: &[u8], digest: &[u8], signature: &str, sequence: u64) -> Result<(), RequestResult> {
+        let link_path_buf = self.entry_path(&hex::encode(pointer));
+        let target = self.entry_path(&hex::encode(digest));
+
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        check_sequence(history_list(&link_path_buf), sequence)?;
+        if let Err(e) = prepare_dir(&link_path_buf).and_then(|_| set_link(&target, &link_path_buf)) {
+            return Err(write_error(e, "cannot update mutable reference"));
+        }
+        let revision = Revision::new(digest.to_vec(), signature, sequence);
+        if let Err(e) = history_append(&link_path_buf, &revision) {
+            return Err(write_error(e, "cannot record revision"));
+        }
+        Ok(())
+    }
+
+    fn set_alias(&self, alias: &[u8], digest: &[u8]) -> Result<(), RequestResult> {
+        let link_path_buf = self.entry_path(&hex::encode(alias));
+        let target = self.entry_path(&hex::encode(digest));
+        if let Err(e) = prepare_dir(&link_path_buf).and_then(|_| set_link(&target, &link_path_buf)) {
+            return Err(write_error(e, "cannot create alias"));
+        }
+        Ok(())
+    }
+
+    fn delete_pointer(&self, pointer: &[u8], signature: &str, sequence: u64) -> Result<(), RequestResult> {
+        let mutable_ref = hex::encode(pointer);
+        let link_path_buf = self.entry_path(&mutable_ref);
+
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        match link_path_buf.symlink_metadata() {
+            Ok(v) if v.file_type().is_symlink() => {},
+            _ => {
+                debug!("no mutable reference {} to delete", mutable_ref);
+                return Err(RequestResult{
+                    typ: RequestResultType::RecordError,
+                    v: None,
+                    f: None,
+                    m: None,
+                });
+            },
+        }
+        check_sequence(history_list(&link_path_buf), sequence)?;
+        if let Err(e) = remove_file(&link_path_buf) {
+            return Err(write_error(e, "cannot remove mutable reference"));
+        }
+        let dir = link_path_buf.parent().unwrap_or(&self.path);
+        if let Err(e) = File::open(dir).and_then(|d| d.sync_all()) {
+            return Err(write_error(e, "cannot sync store"));
+        }
+        let revision = Revision::new(vec!(), signature, sequence);
+        if let Err(e) = history_append(&link_path_buf, &revision) {
+            return Err(write_error(e, "cannot record revision"));
+        }
+        Ok(())
+    }
+
+    fn resolve(&self, key: &[u8]) -> Option<RecordMeta> {
+        resolve_path(&self.entry_path(&hex::encode(key)))
+    }
+
+    fn open_reader(&self, digest: &[u8]) -> Option<Box<dyn RecordReader>> {
+        match File::open(self.entry_path(&hex::encode(digest))) {
+            Ok(f) => {
+                Some(Box::new(f))
+            },
+            Err(_) => {
+                None
+            },
+        }
+    }
+
+    fn history(&self, pointer: &[u8]) -> io::Result<Vec<Revision>> {
+        history_list(&self.entry_path(&hex::encode(pointer)))
+    }
+
+    fn load_usage(&self, identity: &[u8]) -> io::Result<Usage> {
+        load_usage(&self.path, identity)
+    }
+
+    fn save_usage(&self, identity: &[u8], usage: &Usage) -> io::Result<()> {
+        save_usage(&self.path, identity, usage)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        migrate,
+        read_layout,
+        FsStore,
+        Layout,
+    };
+    use crate::hash::HashAlgo;
+    use crate::meta::ContentMeta;
+    use crate::record::{
+        delete_mutable,
+        get_revision,
+        is_deleted,
+        put_immutable,
+        put_mutable,
+        RequestResultType,
+    };
+    use crate::record::get;
+    use crate::store::Store;
+    use std::fs::{
+        create_dir,
+        read,
+        read_dir,
+        read_link,
+        OpenOptions,
+    };
+    use std::io::{
+        Read,
+        Write,
+    };
+    use tempfile::tempdir;
+
+    use log::debug;
+
+    #[test]
+    fn test_immutable() {
+        let d = tempdir().unwrap();
+        let store = FsStore::new(d.path());
+        let b = b"foo";
+        put_immutable(&store, &b[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default()).unwrap();
+
+        let immutable_path_buf = d.path().join("2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae");
+        let immutable_path = immutable_path_buf.as_path();
+        debug!(">>>>> checking immutable path {:?}", immutable_path);
+        assert!(immutable_path.is_file());
+
+        let r = read(immutable_path).unwrap();
+        assert_eq!(r, b.to_vec());
+    }
+
+    #[test]
+    fn test_mutable() {
+        let d = tempdir().unwrap();
+        let store = FsStore::new(d.path());
+        let b = b"foo";
+        let ptr = b"foobar";
+        put_mutable(&store, ptr.to_vec(), &b[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default(), "", 1).unwrap();
+
+        let foobar_hex = hex::encode(ptr);
+        let mutable_path_buf = d.path().join(foobar_hex);
+        let mutable_path = mutable_path_buf.as_path();
+        debug!(">>>>> checking mutable path {:?}", mutable_path);
+        assert!(mutable_path.is_symlink());
+
+        let r = read(mutable_path).unwrap();
+        assert_eq!(r, b.to_vec());
+
+        let immutable_path_buf = d.path().join("2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae");
+        let immutable_path = immutable_path_buf.as_path();
+        debug!(">>>>> checking immutable path {:?}", immutable_path);
+        assert!(immutable_path.is_file());
+
+        let r = read(immutable_path).unwrap();
+        assert_eq!(r, b.to_vec());
+
+        let meta = store.resolve(ptr).unwrap();
+        assert_eq!(hex::encode(meta.digest), "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae");
+    }
+
+    #[test]
+    fn test_mutable_update() {
+        let d = tempdir().unwrap();
+        let store = FsStore::new(d.path());
+        let ptr = b"foobar";
+        put_mutable(&store, ptr.to_vec(), &b"foo"[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default(), "foosig", 1).unwrap();
+        put_mutable(&store, ptr.to_vec(), &b"bar"[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default(), "barsig", 2).unwrap();
+
+        let mutable_path = d.path().join(hex::encode(ptr));
+        assert!(mutable_path.is_symlink());
+        assert_eq!(read(&mutable_path).unwrap(), b"bar".to_vec());
+
+        // both records, the link and its revision log remain, no temporary links are left over
+        assert_eq!(read_dir(d.path()).unwrap().count(), 4);
+
+        let (f, m) = get_revision(&store, ptr, 0).unwrap();
+        assert_eq!(std::io::read_to_string(f).unwrap(), "foo");
+        assert_eq!(hex::encode(m.digest), "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae");
+        assert!(get_revision(&store, ptr, 1).is_ok());
+        assert!(get_revision(&store, ptr, 2).is_err());
+    }
+
+    #[test]
+    fn test_mutable_history_corrupt() {
+        let d = tempdir().unwrap();
+        let store = FsStore::new(d.path());
+        let ptr = b"foobar";
+        put_mutable(&store, ptr.to_vec(), &b"foo"[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default(), "foosig", 1).unwrap();
+        let log_path = d.path().join(format!("{}.log", hex::encode(ptr)));
+        OpenOptions::new().append(true).open(&log_path).unwrap().write_all(b"garbage\n").unwrap();
+        put_mutable(&store, ptr.to_vec(), &b"bar"[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default(), "barsig", 2).unwrap();
+
+        // the corrupt entry keeps its index, so later revisions are not shifted
+        assert!(get_revision(&store, ptr, 1).is_err());
+        let (f, _) = get_revision(&store, ptr, 2).unwrap();
+        assert_eq!(std::io::read_to_string(f).unwrap(), "bar");
+    }
+
+    #[test]
+    fn test_mutable_delete() {
+        let d = tempdir().unwrap();
+        let store = FsStore::new(d.path());
+        let ptr = b"foobar";
+        assert!(delete_mutable(&store, ptr.to_vec(), "foosig", 2).is_err());
+
+        put_mutable(&store, ptr.to_vec(), &b"foo"[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default(), "foosig", 1).unwrap();
+        assert!(!is_deleted(&store, ptr));
+
+        delete_mutable(&store, ptr.to_vec(), "foosig", 2).unwrap();
+        assert!(is_deleted(&store, ptr));
+        assert!(!d.path().join(hex::encode(ptr)).exists());
+        assert!(d.path().join("2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae").is_file());
+        assert!(get_revision(&store, ptr, 0).is_ok());
+        assert!(get_revision(&store, ptr, 1).is_err());
+
+        put_mutable(&store, ptr.to_vec(), &b"bar"[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default(), "barsig", 3).unwrap();
+        assert!(!is_deleted(&store, ptr));
+    }
+
+    #[test]
+    fn test_mutable_update_fail() {
+        let d = tempdir().unwrap();
+        let store = FsStore::new(d.path());
+        let ptr = b"foobar";
+        create_dir(d.path().join(hex::encode(ptr))).unwrap();
+
+        let r = put_mutable(&store, ptr.to_vec(), &b"foo"[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default(), "", 1);
+        assert_eq!(r.err().unwrap().typ, RequestResultType::WriteError);
+    }
+
+    #[test]
+    fn test_immutable_truncated() {
+        let d = tempdir().unwrap();
+        let store = FsStore::new(d.path());
+        let b = b"foo";
+        let r = put_immutable(&store, &b[..], 4, HashAlgo::Sha256, &[], &ContentMeta::default());
+        assert_eq!(r.err().unwrap().typ, RequestResultType::ReadError);
+
+        // nothing is left behind in the store, not even the staged tempfile
+        assert_eq!(read_dir(d.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_immutable_meta() {
+        let d = tempdir().unwrap();
+        let store = FsStore::new(d.path());
+        let b = b"{}";
+        let mut meta = ContentMeta::default();
+        meta.set_type("application/json");
+        meta.set_disposition("attachment; filename=\"empty.json\"");
+        let record = put_immutable(&store, &b[..], 2, HashAlgo::Sha256, &[], &meta).unwrap();
+
+        // content digest is independent of the metadata
+        assert_eq!(hex::encode(&record.digest), "44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a");
+
+        let r = store.resolve(&record.digest).unwrap();
+        assert_eq!(r.content, meta);
+
+        // storing the same content again does not change what is served for it
+        let mut other = ContentMeta::default();
+        other.set_type("text/html");
+        other.set_disposition("attachment; filename=\"index.html\"");
+        put_immutable(&store, &b[..], 2, HashAlgo::Sha256, &[], &other).unwrap();
+        let r = store.resolve(&record.digest).unwrap();
+        assert_eq!(r.content, meta);
+    }
+
+    #[test]
+    fn test_immutable_over_mutable() {
+        let d = tempdir().unwrap();
+        let store = FsStore::new(d.path());
+
+        // the reference is the digest of public inputs, key digest and identity
+        let mut preimage = HashAlgo::Sha256.digest(b"foo");
+        preimage.extend(b"deadbeef");
+        let ptr = HashAlgo::Sha256.digest(&preimage);
+        put_mutable(&store, ptr.clone(), &b"bar"[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default(), "", 1).unwrap();
+
+        let r = put_immutable(&store, &preimage[..], preimage.len(), HashAlgo::Sha256, &[], &ContentMeta::default());
+        assert_eq!(r.err().unwrap().typ, RequestResultType::WriteError);
+
+        assert!(d.path().join(hex::encode(&ptr)).is_symlink());
+        let r = store.resolve(&ptr).unwrap();
+        assert_eq!(hex::encode(r.digest), "fcde2b2edba56bf408601fb721fe9b5c338d10ee429ea04fae5511b68fbf8fb9");
+    }
+
+    #[test]
+    fn test_sharded() {
+        let d = tempdir().unwrap();
+        let store = FsStore::with_layout(d.path(), Layout::Sharded);
+        put_mutable(&store, b"foobar".to_vec(), &b"foo"[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default(), "", 1).unwrap();
+        let pointer = hex::encode(b"foobar");
+
+        let digest = "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae";
+        assert!(d.path().join("2c").join("26").join(digest).is_file());
+        assert!(!d.path().join(digest).exists());
+        let link = store.entry_path(&pointer);
+        assert!(link.starts_with(d.path().join(&pointer[0..2]).join(&pointer[2..4])));
+        assert!(link.with_extension("log").is_file());
+
+        let (mut f, _) = get(&store, &hex::decode(&pointer).unwrap()).unwrap();
+        let mut r = vec!();
+        f.read_to_end(&mut r).unwrap();
+        assert_eq!(r, b"foo".to_vec());
+
+        let name = hex::encode(put_immutable(&store, &b"foo"[..], 3, HashAlgo::Blake3, &[], &ContentMeta::default()).unwrap().digest);
+        assert!(d.path().join("04").join("e0").join(&name).is_file());
+    }
+
+    #[test]
+    fn test_layout_marker() {
+        let d = tempdir().unwrap();
+        FsStore::open(d.path(), None).unwrap();
+        assert_eq!(read_layout(d.path()).unwrap(), Some(Layout::Flat));
+        FsStore::open(d.path(), Some(Layout::Flat)).unwrap();
+        assert!(FsStore::open(d.path(), Some(Layout::Sharded)).is_err());
+
+        let d = tempdir().unwrap();
+        FsStore::open(d.path(), Some(Layout::Sharded)).unwrap();
+        FsStore::open(d.path(), None).unwrap();
+        assert!(FsStore::open(d.path(), Some(Layout::Flat)).is_err());
+
+        // a store from before markers existed is flat
+        let d = tempdir().unwrap();
+        put_immutable(&FsStore::new(d.path()), &b"foo"[..], 3, HashAlgo::Sha256, &[], &ContentMeta::default()).unwrap();
+        assert!(FsStore::open(d.path(), Some(Layout::Sharded)).is_err());
+        assert_eq!(read_layout(d.path()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_migrate() {
+        let d = tempdir().unwrap();
+        let flat = FsStore::new(d.path());
+        let mut meta = ContentMeta::default();
+        meta.set_type("text/plain");
+        put_mutable(&flat, b"foobar".to_vec(), &b"foo"[..], 3, HashAlgo::Sha256, &[], &meta, "", 1).unwrap();
+        let pointer = hex::encode(b"foobar");
+        put_mutable(&flat, b"foobar".to_vec(), &b"bar"[..], 3, HashAlgo::Sha256, &[], &meta, "", 2).unwrap();
+        put_immutable(&flat, &b"baz"[..], 3, HashAlgo::Sha256, &[], &meta).unwrap();
+
+        let r = migrate(d.path()).unwrap();
+        assert_eq!(r.moved, 7);
+        assert_eq!(r.relinked, 1);
+        assert_eq!(read_layout(d.path()).unwrap(), Some(Layout::Sharded));
+        assert!(FsStore::open(d.path(), Some(Layout::Flat)).is_err());
+
+        let store = FsStore::open(d.path(), None).unwrap();
+        let link = store.entry_path(&pointer);
+        assert_eq!(read_link(&link).unwrap(), store.entry_path("fcde2b2edba56bf408601fb721fe9b5c338d10ee429ea04fae5511b68fbf8fb9"));
+        let (mut f, m) = get(&store, &hex::decode(&pointer).unwrap()).unwrap();
+        let mut r = vec!();
+        f.read_to_end(&mut r).unwrap();
+        assert_eq!(r, b"bar".to_vec());
+        assert_eq!(m.content.typ.as_deref(), Some("text/plain"));
+        assert_eq!(store.history(&hex::decode(&pointer).unwrap()).unwrap().len(), 2);
+
+        // nothing left to do
+        let r = migrate(d.path()).unwrap();
+        assert_eq!(r.moved + r.relinked, 0);
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::{
+    Cursor,
+    Read,
+};
+use std::sync::{
+    Mutex,
+    MutexGuard,
+};
+
+use crate::hash::HashAlgo;
+use crate::history::Revision;
+use crate::meta::ContentMeta;
+use crate::quota::Usage;
+use crate::record::{
+    RecordMeta,
+    RequestResult,
+    RequestResultType,
+};
+use crate::store::{
+    check_sequence,
+    RecordReader,
+    Store,
+};
+
+
+#[derive(Default)]
+struct MemState {
+    records: HashMap<Vec<u8>, (Vec<u8>, ContentMeta)>,
+    pointers: HashMap<Vec<u8>, Vec<u8>>,
+    history: HashMap<Vec<u8>, Vec<Revision>>,
+    usage: HashMap<Vec<u8>, Usage>,
+}
+
+/// Store keeping everything in memory, for tests.
+#[derive(Default)]
+pub struct MemStore {
+    state: Mutex<MemState>,
+}
+
+impl MemStore {
+    fn state(&self) -> MutexGuard<'_, MemState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Store for MemStore {
+    fn put_blob(&self, f: &mut dyn Read, expected_size: usize, algo: HashAlgo, meta: &ContentMeta) -> Result<Vec<u8>, RequestResult> {
+        let mut data = vec!();
+        if f.read_to_end(&mut data).is_err() || (expected_size > 0 && expected_size != data.len()) {
+            return Err(RequestResult{
+                typ: RequestResultType::ReadError,
+                v: None,
+                f: None,
+                m: None,
+            });
+        }
+        let digest = algo.name(&algo.digest(&data));
+        let mut state = self.state();
+        if state.pointers.contains_key(&digest) {
+            return Err(RequestResult{
+                typ: RequestResultType::WriteError,
+                v: None,
+                f: None,
+                m: None,
+            });
+        }
+        state.records.entry(digest.clone()).or_insert((data, meta.clone()));
+        Ok(digest)
+    }
+
+    fn set_pointer(&self, pointer: &[u8], digest: &[u8], signature: &str, sequence: u64) -> Result<(), RequestResult> {
+        let mut state = self.state();
+        check_sequence(Ok(state.history.get(pointer).cloned().unwrap_or_default()), sequence)?;
+        state.pointers.insert(pointer.to_vec(), digest.to_vec());
+        state.history.entry(pointer.to_vec()).or_default().push(Revision::new(digest.to_vec(), signature, sequence));
+        Ok(())
+    }
+
+    fn set_alias(&self, alias: &[u8], digest: &[u8]) -> Result<(), RequestResult> {
+        self.state().pointers.insert(alias.to_vec(), digest.to_vec());
+        Ok(())
+    }
+
+    fn delete_pointer(&self, pointer: &[u8], signature: &str, sequence: u64) -> Result<(), RequestResult> {
+        let mut state = self.state();
+        if !state.pointers.contains_key(pointer) {
+            return Err(RequestResult{
+                typ: RequestResultType::RecordError,
+                v: None,
+                f: None,
+                m: None,
+            });
+        }
+        check_sequence(Ok(state.history.get(pointer).cloned().unwrap_or_default()), sequence)?;
+        state.pointers.remove(pointer);
+        state.history.entry(pointer.to_vec()).or_default().push(Revision::new(vec!(), signature, sequence));
+        Ok(())
+    }
+
+    fn resolve(&self, key: &[u8]) -> Option<RecordMeta> {
+        let state = self.state();
+        let digest = state.pointers.get(key).map(|v| v.as_slice()).unwrap_or(key);
+        let (_, content) = state.records.get(digest)?;
+        Some(RecordMeta{
+            digest: digest.to_vec(),
+            content: content.clone(),
+            sequence: 0,
+        })
+    }
+
+    fn open_reader(&self, digest: &[u8]) -> Option<Box<dyn RecordReader>> {
+        let state = self.state();
+        let (data, _) = state.records.get(digest)?;
+        Some(Box::new(Cursor::new(data.clone())))
+    }
+
+    fn history(&self, pointer: &[u8]) -> io::Result<Vec<Revision>> {
+        match self.state().history.get(pointer) {
+            Some(v) => {
+                Ok(v.clone())
+            },
+            None => {
+                Err(io::Error::new(io::ErrorKind::NotFound, "no history"))
+            },
+        }
+    }
+
+    fn load_usage(&self, identity: &[u8]) -> io::Result<Usage> {
+        Ok(self.state().usage.get(identity).cloned().unwrap_or_default())
+    }
+
+    fn save_usage(&self, identity: &[u8], usage: &Usage) -> io::Result<()> {
+        self.state().usage.insert(identity.to_vec(), usage.clone());
+        Ok(())
+    }
+}
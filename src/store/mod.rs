@@ -0,0 +1,181 @@
+use std::fs::File;
+use std::io;
+use std::io::{
+    Cursor,
+    Read,
+    Seek,
+    Write,
+};
+use std::path::Path;
+
+use tempfile::NamedTempFile;
+use log::{debug, info, error};
+
+use crate::hash::HashAlgo;
+use crate::history::Revision;
+use crate::meta::ContentMeta;
+use crate::quota::Usage;
+use crate::record::{
+    write_error,
+    RecordMeta,
+    RequestResult,
+    RequestResultType,
+};
+
+
+/// Content of an immutable record, opened for reading.
+pub trait RecordReader: Read + Seek + Send {
+    fn size(&self) -> io::Result<u64>;
+}
+
+impl RecordReader for File {
+    fn size(&self) -> io::Result<u64> {
+        self.metadata().map(|v| v.len())
+    }
+}
+
+impl RecordReader for Cursor<Vec<u8>> {
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.get_ref().len() as u64)
+    }
+}
+
+/// Copy content into a temporary file in the given directory while computing its digest.
+///
+/// The record name for the digest is returned, see [`HashAlgo::name`].
+///
+/// Content that does not match a non-zero expected size is rejected.
+pub fn stage_blob(f: &mut dyn Read, expected_size: usize, algo: HashAlgo, dir: &Path) -> Result<(NamedTempFile, Vec<u8>), RequestResult> {
+    let mut total_size: usize = 0;
+    let mut of = match NamedTempFile::new_in(dir) {
+        Ok(v) => {
+            v
+        },
+        Err(e) => {
+            return Err(write_error(e, "cannot create tempfile"));
+        },
+    };
+    debug!("writing to tempfile {:?} expected size {}", of.path(), expected_size);
+    let mut buf: [u8; 65535] = [0; 65535];
+    let mut h = algo.hasher();
+    loop {
+        match f.read(&mut buf[..]) {
+            Ok(v) => {
+                if v == 0 {
+                    break;
+                }
+                total_size += v;
+                let data = &buf[..v];
+                h.update(data);
+                if let Err(e) = of.write_all(data) {
+                    return Err(write_error(e, "cannot write to tempfile"));
+                }
+            },
+            Err(e) => {
+                error!("cannot read from request body: {}", e);
+                let err = RequestResult{
+                    typ: RequestResultType::ReadError,
+                    v: None,
+                    f: None,
+                    m: None,
+                };
+                return Err(err);
+            },
+        }
+    }
+
+    if expected_size > 0 && expected_size != total_size {
+        let err = RequestResult{
+            typ: RequestResultType::ReadError,
+            v: None,
+            f: None,
+            m: None,
+        };
+        return Err(err);
+    }
+
+    let z = algo.name(&h.finalize());
+    info!("have {} hash {} for content", algo, hex::encode(&z));
+    Ok((of, z))
+}
+
+/// Check that an update to a mutable reference carries a sequence greater than any in its
+/// revision log.
+///
+/// A reference without a revision log accepts any sequence.
+pub fn check_sequence(history: io::Result<Vec<Revision>>, sequence: u64) -> Result<(), RequestResult> {
+    let current = match history {
+        Ok(v) => {
+            v.iter().map(|v| v.sequence).max().unwrap_or(0)
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            0
+        },
+        Err(e) => {
+            return Err(write_error(e, "cannot read revision log"));
+        },
+    };
+    if sequence <= current {
+        debug!("sequence {} does not exceed current sequence {}", sequence, current);
+        return Err(RequestResult{
+            typ: RequestResultType::Conflict,
+            v: Some(format!("sequence must be greater than {}", current)),
+            f: None,
+            m: None,
+        });
+    }
+    Ok(())
+}
+
+/// Backend holding immutable records, the mutable references pointing to them and the
+/// bookkeeping that goes with both.
+///
+/// Errors are returned as ready made results for the client.
+pub trait Store: Send + Sync {
+    /// Store content as an immutable record and return its name, the digest in the given
+    /// algorithm.
+    ///
+    /// Metadata is only stored along with new content. Content that is already stored keeps the
+    /// metadata it was first stored with, so that uploading it again cannot change what is
+    /// served for existing records and the mutable references pointing to them. Content whose
+    /// name is taken by a mutable reference is refused, rather than replacing the reference.
+    fn put_blob(&self, f: &mut dyn Read, expected_size: usize, algo: HashAlgo, meta: &ContentMeta) -> Result<Vec<u8>, RequestResult>;
+
+    /// Point a mutable reference at a stored record, and append the change to its revision log.
+    ///
+    /// The update is rejected unless its sequence is greater than any the reference had before,
+    /// see [`check_sequence`].
+    fn set_pointer(&self, pointer: &[u8], digest: &[u8], signature: &str, sequence: u64) -> Result<(), RequestResult>;
+
+    /// Make a record reachable under another name, such as its digest in another algorithm.
+    ///
+    /// Unlike mutable references, aliases have no revision log.
+    fn set_alias(&self, alias: &[u8], digest: &[u8]) -> Result<(), RequestResult>;
+
+    /// Remove a mutable reference, and append a tombstone to its revision log.
+    ///
+    /// Sequences are checked as for [`Store::set_pointer`].
+    fn delete_pointer(&self, pointer: &[u8], signature: &str, sequence: u64) -> Result<(), RequestResult>;
+
+    /// Resolve a digest or a mutable reference to the immutable record it refers to.
+    fn resolve(&self, key: &[u8]) -> Option<RecordMeta>;
+
+    /// Open the content of an immutable record.
+    fn open_reader(&self, digest: &[u8]) -> Option<Box<dyn RecordReader>>;
+
+    /// All revisions of a mutable reference, oldest first.
+    fn history(&self, pointer: &[u8]) -> io::Result<Vec<Revision>>;
+
+    fn load_usage(&self, identity: &[u8]) -> io::Result<Usage>;
+
+    fn save_usage(&self, identity: &[u8], usage: &Usage) -> io::Result<()>;
+}
+
+
+pub mod fs;
+
+#[cfg(feature = "s3")]
+pub mod s3;
+
+#[cfg(test)]
+pub mod mem;
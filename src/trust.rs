@@ -0,0 +1,336 @@
+use std::collections::HashSet;
+use std::fs::{
+    metadata,
+    read,
+    read_dir,
+};
+use std::io;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::Mutex;
+use std::time::{
+    Duration,
+    Instant,
+    SystemTime,
+};
+
+use log::{debug, info, error};
+
+#[cfg(feature = "pgpauth")]
+use crate::auth::pgp::keyring_fingerprints;
+
+/// Shortest time between checks whether the store changed.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+
+/// Identities allowed to authenticate, read from a file or a directory of files.
+///
+/// A file is either an OpenPGP keyring, armored or binary, whose primary keys are trusted by
+/// fingerprint, or a list of identities. A list holds one identity per line in hex, as produced
+/// by the auth method that verified it: a PGP fingerprint, an ed25519 public key or an eth
+/// address. Anything after the identity on the same line is ignored, as are empty lines and lines
+/// starting with `#`. In a directory, files whose names start with `.` are skipped.
+///
+/// The store is read again when its modification time changes, checked at most once every
+/// [`REFRESH_INTERVAL`], so identities can be added and removed without restarting.
+pub struct TrustStore {
+    path: PathBuf,
+    interval: Duration,
+    state: Mutex<TrustState>,
+}
+
+struct TrustState {
+    stamp: Stamp,
+    checked: Instant,
+    identities: HashSet<Vec<u8>>,
+}
+
+/// Latest modification time and number of files making up the store.
+type Stamp = (Option<SystemTime>, usize);
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Parse the identities listed in the contents of a trust store file.
+pub fn parse(s: &str) -> Result<Vec<Vec<u8>>, String> {
+    let mut r = vec!();
+    for l in s.lines() {
+        let l = l.trim();
+        if l.is_empty() || l.starts_with('#') {
+            continue;
+        }
+        let v = l.split_whitespace().next().unwrap();
+        match hex::decode(v.strip_prefix("0x").unwrap_or(v)) {
+            Ok(v) if !v.is_empty() => {
+                r.push(v);
+            },
+            _ => {
+                return Err(format!("invalid identity {:?}", v));
+            },
+        };
+    }
+    Ok(r)
+}
+
+#[cfg(feature = "pgpauth")]
+fn parse_keyring(data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    keyring_fingerprints(data)
+}
+
+#[cfg(not(feature = "pgpauth"))]
+fn parse_keyring(_data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    Err(String::from("OpenPGP keyrings need the pgpauth feature"))
+}
+
+/// Parse the identities in a trust store file, a keyring or a list.
+fn parse_file(data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    match std::str::from_utf8(data) {
+        Ok(s) if !s.trim_start().starts_with("-----BEGIN PGP") => {
+            parse(s)
+        },
+        Ok(s) => {
+            parse_keyring(s.trim_start().as_bytes())
+        },
+        Err(_) => {
+            parse_keyring(data)
+        },
+    }
+}
+
+/// Files making up the store, in a stable order.
+fn files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if !metadata(path)?.is_dir() {
+        return Ok(vec!(path.to_path_buf()));
+    }
+    let mut r = vec!();
+    for v in read_dir(path)? {
+        let v = v?;
+        if v.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if v.file_type()?.is_dir() {
+            continue;
+        }
+        r.push(v.path());
+    }
+    r.sort();
+    Ok(r)
+}
+
+/// Modification stamp of the store, including the directory itself so removals are noticed.
+fn stamp(path: &Path) -> io::Result<Stamp> {
+    let files = files(path)?;
+    let mut modified = metadata(path)?.modified().ok();
+    for v in &files {
+        let m = metadata(v)?.modified().ok();
+        if m > modified {
+            modified = m;
+        }
+    }
+    Ok((modified, files.len()))
+}
+
+fn load(path: &Path) -> io::Result<HashSet<Vec<u8>>> {
+    let mut r = HashSet::new();
+    for v in files(path)? {
+        match parse_file(&read(&v)?) {
+            Ok(identities) => {
+                r.extend(identities);
+            },
+            Err(e) => {
+                return Err(invalid(format!("{:?}: {}", v, e)));
+            },
+        };
+    }
+    Ok(r)
+}
+
+impl TrustStore {
+    /// Read the store at the given path, which must exist and parse.
+    pub fn open(path: &Path) -> io::Result<TrustStore> {
+        TrustStore::with_interval(path, REFRESH_INTERVAL)
+    }
+
+    /// Read the store, checking for changes at most once per `interval`.
+    pub fn with_interval(path: &Path, interval: Duration) -> io::Result<TrustStore> {
+        let stamp = stamp(path)?;
+        let identities = load(path)?;
+        info!("trusting {} identities from {:?}", identities.len(), path);
+        let state = TrustState {
+            stamp,
+            checked: Instant::now(),
+            identities,
+        };
+        Ok(TrustStore {
+            path: path.to_path_buf(),
+            interval,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Read the store again if it changed since it was last read.
+    ///
+    /// If the changed store cannot be read, the identities read before are kept.
+    fn refresh(&self, state: &mut TrustState) {
+        if state.checked.elapsed() < self.interval {
+            return;
+        }
+        state.checked = Instant::now();
+        let stamp = match stamp(&self.path) {
+            Ok(v) => {
+                v
+            },
+            Err(e) => {
+                error!("cannot check trust store {:?}, keeping previous identities: {}", self.path, e);
+                return;
+            },
+        };
+        if stamp == state.stamp {
+            return;
+        }
+        match load(&self.path) {
+            Ok(v) => {
+                info!("reloaded trust store {:?}, trusting {} identities", self.path, v.len());
+                state.identities = v;
+                state.stamp = stamp;
+            },
+            Err(e) => {
+                error!("cannot reload trust store {:?}, keeping previous identities: {}", self.path, e);
+            },
+        };
+    }
+
+    /// Whether the identity is listed in the store, as it was on disk at the last check.
+    pub fn allows(&self, identity: &[u8]) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        self.refresh(&mut state);
+        let r = state.identities.contains(identity);
+        debug!("identity {:?} trusted: {}", hex::encode(identity), r);
+        r
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse,
+        TrustStore,
+    };
+    use std::fs::{
+        remove_file,
+        write,
+        File,
+    };
+    use std::time::{
+        Duration,
+        SystemTime,
+    };
+    use tempfile::tempdir;
+
+    #[cfg(feature = "pgpauth")]
+    const KEYRING: &str = "-----BEGIN PGP PUBLIC KEY BLOCK-----
+
+mDMEatQyGRYJKwYBBAHaRw8BAQdAzHRSM9EkI3oRvheJz0+v+sdfnjAQGAtXji9b
+KK5iAFq0HXdhbGEgdGVzdCBvbmUgPG9uZUB3YWxhLnRlc3Q+iJAEExYIADgWIQRW
+DbMZraqSgs3EaalxWxbymRSGwwUCatQyGQIbAwULCQgHAgYVCgkICwIEFgIDAQIe
+AQIXgAAKCRBxWxbymRSGw+zVAP0ac1SKpiKOr40GoM7cSphlwsiM1dKIZUsJhmXA
++t4ZTgEA9HRJp+P5s0z5Hjyb+khd9egq04Uo93WD1cvKHChhlAaYMwRq1DIZFgkr
+BgEEAdpHDwEBB0DUzWJbLqXkyYf6FX2kJyJHVSM9m2lf1afXouItMNipR7Qdd2Fs
+YSB0ZXN0IHR3byA8dHdvQHdhbGEudGVzdD6IkAQTFggAOBYhBB9Kl/Em/tvBaXKK
+v4kUj6FCrAKmBQJq1DIZAhsDBQsJCAcCBhUKCQgLAgQWAgMBAh4BAheAAAoJEIkU
+j6FCrAKmsHMBAJ77gx0oxkH3T2LQWRA6Jsv4MltoNBU4Y3ltBOpzNyKTAP0ZNHkD
+z+lyzBMrhcKmKbau+OHsAGNBk+zZlpJZNmZNDA==
+=aZ8K
+-----END PGP PUBLIC KEY BLOCK-----
+";
+
+    #[test]
+    fn test_trust_parse() {
+        let r = parse("# keys\n\n666f6f alice\n0x626172\n").unwrap();
+        assert_eq!(r, vec!(b"foo".to_vec(), b"bar".to_vec()));
+        assert!(parse("xyz\n").is_err());
+    }
+
+    #[test]
+    fn test_trust_reload() {
+        let d = tempdir().unwrap();
+        let p = d.path().join("trusted");
+        write(&p, "666f6f\n").unwrap();
+
+        let trust = TrustStore::with_interval(&p, Duration::ZERO).unwrap();
+        assert!(trust.allows(b"foo"));
+        assert!(!trust.allows(b"bar"));
+
+        // set mtime explicitly, as rewrites within the timestamp resolution would go unnoticed
+        write(&p, "626172\n").unwrap();
+        let f = File::options().write(true).open(&p).unwrap();
+        f.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+        assert!(!trust.allows(b"foo"));
+        assert!(trust.allows(b"bar"));
+
+        // a broken store keeps the identities read before
+        write(&p, "xyz\n").unwrap();
+        f.set_modified(SystemTime::now() + Duration::from_secs(20)).unwrap();
+        assert!(trust.allows(b"bar"));
+    }
+
+    #[test]
+    fn test_trust_dir() {
+        let d = tempdir().unwrap();
+        write(d.path().join("alice"), "666f6f\n").unwrap();
+        write(d.path().join(".alice.swp"), "xyz\n").unwrap();
+
+        let trust = TrustStore::with_interval(d.path(), Duration::ZERO).unwrap();
+        assert!(trust.allows(b"foo"));
+        assert!(!trust.allows(b"bar"));
+
+        write(d.path().join("bob"), "626172\n").unwrap();
+        assert!(trust.allows(b"bar"));
+
+        remove_file(d.path().join("alice")).unwrap();
+        assert!(!trust.allows(b"foo"));
+    }
+
+    #[test]
+    fn test_trust_interval() {
+        let d = tempdir().unwrap();
+        let p = d.path().join("trusted");
+        write(&p, "666f6f\n").unwrap();
+
+        let trust = TrustStore::with_interval(&p, Duration::from_secs(3600)).unwrap();
+        write(&p, "626172\n").unwrap();
+        let f = File::options().write(true).open(&p).unwrap();
+        f.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+        assert!(trust.allows(b"foo"));
+        assert!(!trust.allows(b"bar"));
+    }
+
+    #[test]
+    #[cfg(feature = "pgpauth")]
+    fn test_trust_keyring() {
+        let one = hex::decode("560DB319ADAA9282CDC469A9715B16F2991486C3").unwrap();
+        let two = hex::decode("1F4A97F126FEDBC169728ABF89148FA142AC02A6").unwrap();
+
+        let d = tempdir().unwrap();
+        write(d.path().join("ring.asc"), KEYRING).unwrap();
+        let trust = TrustStore::with_interval(d.path(), Duration::ZERO).unwrap();
+        assert!(trust.allows(&one));
+        assert!(trust.allows(&two));
+
+        // same keyring, unarmored
+        let body: String = KEYRING.lines()
+            .skip_while(|l| !l.is_empty())
+            .filter(|l| !l.is_empty() && !l.starts_with('=') && !l.starts_with("-----"))
+            .collect();
+        let d = tempdir().unwrap();
+        write(d.path().join("ring.gpg"), base64::decode(body).unwrap()).unwrap();
+        let trust = TrustStore::with_interval(d.path(), Duration::ZERO).unwrap();
+        assert!(trust.allows(&one));
+        assert!(trust.allows(&two));
+        assert!(!trust.allows(b"foo"));
+    }
+}
@@ -0,0 +1,192 @@
+use std::io::{
+    Read,
+    Write,
+};
+use std::net::{
+    TcpListener,
+    TcpStream,
+};
+use std::path::Path;
+use std::process::{
+    Child,
+    Command,
+};
+use std::thread::sleep;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Status, headers and body of a response, read until the server closed the connection.
+struct Reply {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+}
+
+
+fn free_port() -> u16 {
+    let l = TcpListener::bind("127.0.0.1:0").unwrap();
+    l.local_addr().unwrap().port()
+}
+
+fn connect(port: u16) -> TcpStream {
+    for _ in 0..50 {
+        match TcpStream::connect(("127.0.0.1", port)) {
+            Ok(v) => {
+                return v;
+            },
+            Err(_) => {
+                sleep(Duration::from_millis(100));
+            },
+        }
+    }
+    panic!("server did not come up on port {}", port);
+}
+
+fn spawn_wala(datadir: &Path, args: &[&str]) -> (Server, u16) {
+    let port = free_port();
+    let child = Command::new(env!("CARGO_BIN_EXE_wala"))
+        .arg("--host").arg("127.0.0.1")
+        .arg("--port").arg(port.to_string())
+        .arg("--datadir").arg(datadir)
+        .args(args)
+        .spawn()
+        .unwrap();
+    let srv = Server(child);
+    drop(connect(port));
+    (srv, port)
+}
+
+/// Send a request on a new connection, with the given header lines and body.
+fn send(port: u16, request_line: &str, headers: &[&str], body: &[u8]) -> Reply {
+    let mut stream = connect(port);
+    let mut head = format!("{}\r\nHost: localhost\r\nConnection: close\r\n", request_line);
+    for v in headers {
+        head.push_str(v);
+        head.push_str("\r\n");
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).unwrap();
+    let _ = stream.write_all(body);
+
+    let mut rsp = vec!();
+    let _ = stream.read_to_end(&mut rsp);
+    let split = rsp.windows(4).position(|v| v == b"\r\n\r\n").expect("incomplete response");
+    let head = String::from_utf8(rsp[..split].to_vec()).unwrap();
+    let mut lines = head.split("\r\n");
+    let status = lines.next().unwrap().split(' ').nth(1).unwrap().parse().unwrap();
+    let headers = lines.map(|l| {
+        let (k, v) = l.split_once(':').unwrap();
+        (k.trim().to_string(), v.trim().to_string())
+    }).collect();
+    Reply {
+        status,
+        headers,
+        body: rsp[split + 4..].to_vec(),
+    }
+}
+
+fn put(port: u16, data: &[u8]) -> String {
+    let content_length = format!("Content-Length: {}", data.len());
+    let r = send(port, "PUT / HTTP/1.1", &[&content_length], data);
+    assert_eq!(r.status, 200);
+    String::from_utf8(r.body).unwrap()
+}
+
+
+#[test]
+fn test_http_head() {
+    let d = tempdir().unwrap();
+    let (_srv, port) = spawn_wala(d.path(), &[]);
+    let digest = put(port, b"hello world");
+
+    let get = send(port, &format!("GET /{} HTTP/1.1", digest), &[], b"");
+    assert_eq!(get.status, 200);
+    assert_eq!(get.body, b"hello world");
+
+    let head = send(port, &format!("HEAD /{} HTTP/1.1", digest), &[], b"");
+    assert_eq!(head.status, 200);
+    assert!(head.body.is_empty());
+    assert_eq!(head.header("Content-Length"), Some("11"));
+    assert_eq!(head.header("Content-Length"), get.header("Content-Length"));
+    assert_eq!(head.header("X-Wala-Digest"), Some(digest.as_str()));
+    assert_eq!(head.header("ETag"), Some(format!("\"{}\"", digest).as_str()));
+    assert_eq!(head.header("ETag"), get.header("ETag"));
+    assert!(head.header("Transfer-Encoding").is_none());
+
+    let missing = send(port, &format!("HEAD /{} HTTP/1.1", "00".repeat(32)), &[], b"");
+    assert_eq!(missing.status, 404);
+}
+
+#[test]
+fn test_http_range() {
+    let d = tempdir().unwrap();
+    let (_srv, port) = spawn_wala(d.path(), &[]);
+    let digest = put(port, b"hello world");
+    let request_line = format!("GET /{} HTTP/1.1", digest);
+
+    let r = send(port, &request_line, &["Range: bytes=0-4"], b"");
+    assert_eq!(r.status, 206);
+    assert_eq!(r.header("Content-Range"), Some("bytes 0-4/11"));
+    assert_eq!(r.header("Content-Length"), Some("5"));
+    assert_eq!(r.header("Accept-Ranges"), Some("bytes"));
+    assert_eq!(r.body, b"hello");
+
+    let r = send(port, &request_line, &["Range: bytes=-5"], b"");
+    assert_eq!(r.status, 206);
+    assert_eq!(r.header("Content-Range"), Some("bytes 6-10/11"));
+    assert_eq!(r.body, b"world");
+
+    let r = send(port, &request_line, &["Range: bytes=11-"], b"");
+    assert_eq!(r.status, 416);
+    assert_eq!(r.header("Content-Range"), Some("bytes */11"));
+    assert!(r.body.is_empty());
+
+    let r = send(port, &request_line, &["Range: bytes=0-4", "If-Range: \"00\""], b"");
+    assert_eq!(r.status, 200);
+    assert_eq!(r.body, b"hello world");
+}
+
+#[test]
+fn test_http_upload_limit() {
+    let d = tempdir().unwrap();
+    let (_srv, port) = spawn_wala(d.path(), &["--max-upload", "8", "--max-upload-auth", "16"]);
+
+    let r = send(port, "PUT / HTTP/1.1", &["Content-Length: 8"], b"12345678");
+    assert_eq!(r.status, 200);
+
+    // declared size over the limit
+    let r = send(port, "PUT / HTTP/1.1", &["Content-Length: 9"], b"123456789");
+    assert_eq!(r.status, 413);
+
+    // chunked body cut off at the limit
+    let r = send(port, "PUT / HTTP/1.1", &["Transfer-Encoding: chunked"], b"9\r\n123456789\r\n0\r\n\r\n");
+    assert_eq!(r.status, 413);
+    let r = send(port, "PUT / HTTP/1.1", &["Transfer-Encoding: chunked"], b"8\r\n12345678\r\n0\r\n\r\n");
+    assert_eq!(r.status, 200);
+
+    // a signature that does not authenticate does not lift the anonymous limit
+    let auth = "Authorization: PUBSIG foo:bar:baz";
+    let r = send(port, "PUT / HTTP/1.1", &[auth, "Content-Length: 9"], b"123456789");
+    assert_eq!(r.status, 413);
+    let r = send(port, "PUT / HTTP/1.1", &[auth, "Transfer-Encoding: chunked"], b"9\r\n123456789\r\n0\r\n\r\n");
+    assert_eq!(r.status, 413);
+    let r = send(port, "PUT / HTTP/1.1", &[auth, "Content-Length: 17"], b"12345678901234567");
+    assert_eq!(r.status, 413);
+}
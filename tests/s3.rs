@@ -0,0 +1,266 @@
+#![cfg(feature = "s3")]
+
+use std::collections::HashMap;
+use std::fs::read_dir;
+use std::net::{
+    TcpListener,
+    TcpStream,
+};
+use std::process::{
+    Child,
+    Command,
+};
+use std::sync::{
+    Arc,
+    Mutex,
+};
+use std::thread;
+use std::thread::sleep;
+use std::time::Duration;
+
+use sha2::{
+    Digest,
+    Sha256,
+};
+use tempfile::tempdir;
+use tiny_http::{
+    Header,
+    Method,
+    Response,
+    Server as HttpServer,
+    StatusCode,
+};
+
+
+type Bucket = Arc<Mutex<HashMap<String, (Vec<u8>, String)>>>;
+
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+
+fn header<'a>(req: &'a tiny_http::Request, name: &'static str) -> Option<&'a str> {
+    req.headers().iter().find(|h| h.field.equiv(name)).map(|h| h.value.as_str())
+}
+
+/// Minimal stand-in for an S3 compatible service, keeping objects in memory by request path.
+///
+/// Requests must carry a signature, but it is not verified.
+fn fake_s3() -> (u16, Bucket) {
+    let srv = HttpServer::http("127.0.0.1:0").unwrap();
+    let port = srv.server_addr().port();
+    let bucket: Bucket = Arc::new(Mutex::new(HashMap::new()));
+    let objects = bucket.clone();
+    thread::spawn(move || {
+        for mut req in srv.incoming_requests() {
+            let signed = header(&req, "Authorization").map(|v| v.starts_with("AWS4-HMAC-SHA256 Credential=foo/")).unwrap_or(false);
+            if !signed || header(&req, "x-amz-date").is_none() {
+                let _ = req.respond(Response::empty(StatusCode(403)));
+                continue;
+            }
+            let key = req.url().to_string();
+            match req.method() {
+                Method::Put => {
+                    let typ = header(&req, "Content-Type").unwrap_or("application/octet-stream").to_string();
+                    let mut data = vec!();
+                    req.as_reader().read_to_end(&mut data).unwrap();
+                    objects.lock().unwrap().insert(key, (data, typ));
+                    let _ = req.respond(Response::empty(StatusCode(200)));
+                },
+                Method::Delete => {
+                    objects.lock().unwrap().remove(&key);
+                    let _ = req.respond(Response::empty(StatusCode(204)));
+                },
+                Method::Get | Method::Head => {
+                    let object = objects.lock().unwrap().get(&key).cloned();
+                    let (data, typ) = match object {
+                        Some(v) => {
+                            v
+                        },
+                        None => {
+                            let _ = req.respond(Response::empty(StatusCode(404)));
+                            continue;
+                        },
+                    };
+                    let start = header(&req, "Range")
+                        .and_then(|v| v.strip_prefix("bytes="))
+                        .and_then(|v| v.strip_suffix('-'))
+                        .map(|v| v.parse::<usize>().unwrap());
+                    let status = match start {
+                        Some(_) => {
+                            StatusCode(206)
+                        },
+                        None => {
+                            StatusCode(200)
+                        },
+                    };
+                    let body = data[start.unwrap_or(0)..].to_vec();
+                    let headers = vec!(Header::from_bytes(&b"Content-Type"[..], typ.as_bytes()).unwrap());
+                    let len = body.len();
+                    let res = Response::new(status, headers, std::io::Cursor::new(body), Some(len), None)
+                        .with_chunked_threshold(usize::MAX);
+                    let _ = req.respond(res);
+                },
+                _ => {
+                    let _ = req.respond(Response::empty(StatusCode(405)));
+                },
+            }
+        }
+    });
+    (port, bucket)
+}
+
+fn free_port() -> u16 {
+    let l = TcpListener::bind("127.0.0.1:0").unwrap();
+    l.local_addr().unwrap().port()
+}
+
+fn wait_for(port: u16) {
+    for _ in 0..50 {
+        if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            return;
+        }
+        sleep(Duration::from_millis(100));
+    }
+    panic!("server did not come up on port {}", port);
+}
+
+fn spawn_wala(s3_port: u16, datadir: &std::path::Path) -> (Server, String) {
+    let port = free_port();
+    let child = Command::new(env!("CARGO_BIN_EXE_wala"))
+        .arg("--host").arg("127.0.0.1")
+        .arg("--port").arg(port.to_string())
+        .arg("--datadir").arg(datadir)
+        .arg("--s3-endpoint").arg(format!("http://127.0.0.1:{}", s3_port))
+        .arg("--s3-bucket").arg("wala")
+        .arg("--s3-access-key").arg("foo")
+        .arg("--s3-secret-key").arg("bar")
+        .spawn()
+        .unwrap();
+    let srv = Server(child);
+    wait_for(port);
+    (srv, format!("http://127.0.0.1:{}", port))
+}
+
+
+#[test]
+fn test_s3_put_get() {
+    let d = tempdir().unwrap();
+    let (s3_port, bucket) = fake_s3();
+    let (_srv, url) = spawn_wala(s3_port, d.path());
+
+    let digest = ureq::put(&format!("{}/", url))
+        .set("Content-Type", "text/plain")
+        .send_bytes(b"hello world").unwrap()
+        .into_string().unwrap();
+    assert_eq!(digest, "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9");
+    {
+        let objects = bucket.lock().unwrap();
+        assert_eq!(objects.get(&format!("/wala/{}", digest)).unwrap().0, b"hello world".to_vec());
+        assert!(objects.contains_key(&format!("/wala/{}.meta", digest)));
+    }
+    // nothing is kept locally
+    assert_eq!(read_dir(d.path()).unwrap().count(), 0);
+
+    let r = ureq::get(&format!("{}/{}", url, digest)).call().unwrap();
+    assert_eq!(r.content_type(), "text/plain");
+    assert_eq!(r.into_string().unwrap(), "hello world");
+
+    // uploading the same content again keeps the metadata it was first stored with
+    ureq::put(&format!("{}/", url))
+        .set("Content-Type", "text/html")
+        .send_bytes(b"hello world").unwrap();
+    let r = ureq::get(&format!("{}/{}", url, digest)).call().unwrap();
+    assert_eq!(r.content_type(), "text/plain");
+    // and is not uploaded again
+    bucket.lock().unwrap().get_mut(&format!("/wala/{}", digest)).unwrap().1 = String::from("x-test/stored");
+    ureq::put(&format!("{}/", url)).send_bytes(b"hello world").unwrap();
+    assert_eq!(bucket.lock().unwrap().get(&format!("/wala/{}", digest)).unwrap().1, "x-test/stored");
+
+    let r = ureq::get(&format!("{}/{}", url, digest)).set("Range", "bytes=6-").call().unwrap();
+    assert_eq!(r.status(), 206);
+    assert_eq!(r.into_string().unwrap(), "world");
+
+    let r = ureq::head(&format!("{}/{}", url, digest)).call().unwrap();
+    assert_eq!(r.header("Content-Length"), Some("11"));
+
+    match ureq::get(&format!("{}/{}", url, "00".repeat(32))).call() {
+        Err(ureq::Error::Status(code, _)) => {
+            assert_eq!(code, 404);
+        },
+        _ => {
+            panic!("expected missing record");
+        },
+    }
+}
+
+#[cfg(feature = "dev")]
+#[test]
+fn test_s3_mutable() {
+    let d = tempdir().unwrap();
+    let (s3_port, bucket) = fake_s3();
+    let (_srv, url) = spawn_wala(s3_port, d.path());
+    let auth = "PUBSIG mock:foo:foo";
+
+    let mut pointer = String::new();
+    for (sequence, data) in [("1", "foo"), ("2", "bar")] {
+        pointer = ureq::put(&format!("{}/key", url))
+            .set("Authorization", auth)
+            .set("X-Wala-Sequence", sequence)
+            .send_bytes(data.as_bytes()).unwrap()
+            .into_string().unwrap();
+    }
+    {
+        let objects = bucket.lock().unwrap();
+        let (target, typ) = objects.get(&format!("/wala/{}", pointer)).unwrap();
+        assert_eq!(typ, "application/x-wala-pointer");
+        assert_eq!(target, b"fcde2b2edba56bf408601fb721fe9b5c338d10ee429ea04fae5511b68fbf8fb9");
+    }
+
+    let r = ureq::get(&format!("{}/{}", url, pointer)).call().unwrap();
+    assert_eq!(r.header("X-Wala-Sequence"), Some("2"));
+    assert_eq!(r.into_string().unwrap(), "bar");
+
+    // content hashing to the reference, key digest and identity, cannot replace it
+    let mut preimage = Sha256::digest(b"key").to_vec();
+    preimage.extend(b"foo");
+    assert_eq!(hex::encode(Sha256::digest(&preimage)), pointer);
+    match ureq::put(&format!("{}/", url)).send_bytes(&preimage) {
+        Err(ureq::Error::Status(code, _)) => {
+            assert_eq!(code, 500);
+        },
+        _ => {
+            panic!("expected upload over reference to be refused");
+        },
+    }
+    assert_eq!(bucket.lock().unwrap().get(&format!("/wala/{}", pointer)).unwrap().1, "application/x-wala-pointer");
+    let r = ureq::get(&format!("{}/{}", url, pointer)).call().unwrap();
+    assert_eq!(r.into_string().unwrap(), "bar");
+
+    match ureq::put(&format!("{}/key", url)).set("Authorization", auth).set("X-Wala-Sequence", "1").send_bytes(b"foo") {
+        Err(ureq::Error::Status(code, _)) => {
+            assert_eq!(code, 409);
+        },
+        _ => {
+            panic!("expected rollback to be rejected");
+        },
+    }
+
+    let r = ureq::get(&format!("{}/{}/history", url, pointer)).call().unwrap();
+    assert_eq!(r.into_string().unwrap().lines().count(), 2);
+
+    ureq::delete(&format!("{}/key", url)).set("Authorization", auth).set("X-Wala-Sequence", "3").send(std::io::empty()).unwrap();
+    match ureq::get(&format!("{}/{}", url, pointer)).call() {
+        Err(ureq::Error::Status(code, _)) => {
+            assert_eq!(code, 410);
+        },
+        _ => {
+            panic!("expected deleted reference");
+        },
+    }
+}
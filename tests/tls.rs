@@ -0,0 +1,140 @@
+#![cfg(feature = "ssl")]
+
+use std::fs::write;
+use std::io::{
+    Read,
+    Write,
+};
+use std::net::{
+    TcpListener,
+    TcpStream,
+};
+use std::path::Path;
+use std::process::{
+    Child,
+    Command,
+};
+use std::thread::sleep;
+use std::time::Duration;
+
+use openssl::asn1::Asn1Time;
+use openssl::bn::{
+    BigNum,
+    MsbOption,
+};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::ssl::{
+    SslConnector,
+    SslMethod,
+};
+use openssl::x509::{
+    X509,
+    X509NameBuilder,
+};
+use openssl::x509::extension::SubjectAlternativeName;
+use tempfile::tempdir;
+
+
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+
+fn self_signed(cert_path: &Path, key_path: &Path) {
+    let rsa = Rsa::generate(2048).unwrap();
+    let key = PKey::from_rsa(rsa).unwrap();
+
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", "localhost").unwrap();
+    let name = name.build();
+
+    let mut serial = BigNum::new().unwrap();
+    serial.rand(64, MsbOption::MAYBE_ZERO, false).unwrap();
+
+    let mut builder = X509::builder().unwrap();
+    builder.set_version(2).unwrap();
+    builder.set_serial_number(&serial.to_asn1_integer().unwrap()).unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_issuer_name(&name).unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+    builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+    let san = SubjectAlternativeName::new()
+        .dns("localhost")
+        .build(&builder.x509v3_context(None, None))
+        .unwrap();
+    builder.append_extension(san).unwrap();
+    builder.sign(&key, MessageDigest::sha256()).unwrap();
+    let cert = builder.build();
+
+    write(cert_path, cert.to_pem().unwrap()).unwrap();
+    write(key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+}
+
+fn free_port() -> u16 {
+    let l = TcpListener::bind("127.0.0.1:0").unwrap();
+    l.local_addr().unwrap().port()
+}
+
+fn connect(port: u16) -> TcpStream {
+    for _ in 0..50 {
+        match TcpStream::connect(("127.0.0.1", port)) {
+            Ok(v) => {
+                return v;
+            },
+            Err(_) => {
+                sleep(Duration::from_millis(100));
+            },
+        }
+    }
+    panic!("server did not come up on port {}", port);
+}
+
+
+#[test]
+fn test_tls_put_get() {
+    let d = tempdir().unwrap();
+    let store = d.path().join("store");
+    std::fs::create_dir(&store).unwrap();
+    let cert_path = d.path().join("cert.pem");
+    let key_path = d.path().join("key.pem");
+    self_signed(&cert_path, &key_path);
+
+    let port = free_port();
+    let child = Command::new(env!("CARGO_BIN_EXE_wala"))
+        .arg("--host").arg("127.0.0.1")
+        .arg("--port").arg(port.to_string())
+        .arg("--datadir").arg(&store)
+        .arg("--tls-cert").arg(&cert_path)
+        .arg("--tls-key").arg(&key_path)
+        .spawn()
+        .unwrap();
+    let _srv = Server(child);
+
+    let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+    connector.set_ca_file(&cert_path).unwrap();
+    let connector = connector.build();
+
+    let stream = connect(port);
+    let mut tls = connector.connect("localhost", stream).unwrap();
+    tls.write_all(b"PUT / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\nConnection: close\r\n\r\nfoo").unwrap();
+    let mut rsp = String::new();
+    let _ = tls.read_to_string(&mut rsp);
+    assert!(rsp.starts_with("HTTP/1.1 200"), "{}", rsp);
+    assert!(rsp.ends_with("2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae"), "{}", rsp);
+
+    let stream = connect(port);
+    let mut tls = connector.connect("localhost", stream).unwrap();
+    tls.write_all(b"GET /2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+    let mut rsp = String::new();
+    let _ = tls.read_to_string(&mut rsp);
+    assert!(rsp.starts_with("HTTP/1.1 200"), "{}", rsp);
+    assert!(rsp.ends_with("\r\n\r\nfoo"), "{}", rsp);
+}